@@ -1,74 +1,274 @@
-use image::{DynamicImage, Pixel, Rgb, Rgba};
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use crate::Pref;
+use ab_glyph::FontArc;
+use anyhow::{anyhow, Result};
+use image::{DynamicImage, Pixel, Rgb, Rgba, RgbaImage};
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use rayon::slice::{ParallelSlice, ParallelSliceMut};
 
-const BASE_IMG_PATH: &str = "./data/maps/full.png";
+use crate::label::{draw_labels, Label};
+use crate::legend::draw_legend;
+use crate::pref::chamfer_distance;
+use crate::text::draw_text;
+use crate::{
+    Assets, LegendOptions, LegendRow, Pref, PrefGeometries, PrefGeometry, PrefMasks, TextStyle,
+    Transition,
+};
+
+// 今回塗った都道府県のふちを光らせる設定
+#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+pub struct Highlight {
+    pub color: Rgb<u8>,
+    // 光の広がり (px)
+    pub width: u32,
+}
+
+// ふちの光だけを描いた透明な画像。光の届く範囲に切り詰めてあり、(x, y)は地図全体の中での左上の位置
+#[derive(Debug, Clone)]
+pub struct Glow {
+    pub x: u32,
+    pub y: u32,
+    pub layer: RgbaImage,
+}
 
 pub struct PrefImgGenerator {
     img: DynamicImage,
     size: u32,
+    masks: PrefMasks,
+    // 同じ都道府県を何度塗っても、光は1回だけ作る。
+    glows: HashMap<(Pref, Highlight), Arc<Glow>>,
+    geometries: PrefGeometries,
+    font: Option<FontArc>,
 }
 
 impl PrefImgGenerator {
-    pub fn new(size: u32) -> Self {
-        let img = image::open(BASE_IMG_PATH)
-            .expect("failed to open img.")
+    pub fn new(assets: &Assets, size: u32) -> Result<Self> {
+        let masks = PrefMasks::new(assets);
+        let img = assets
+            .base_img()?
             .resize(size, size, image::imageops::FilterType::CatmullRom);
 
-        Self { img, size }
+        Ok(Self {
+            img,
+            size,
+            masks: masks.clone(),
+            glows: HashMap::new(),
+            geometries: PrefGeometries::new(&masks),
+            font: None,
+        })
     }
 
-    pub fn overlay(&mut self, pref: &Pref, tint_color: &Rgb<u8>) {
-        let pref_img = image::open(format!("./data/maps/{}.png", pref.as_key()))
-            .expect("failed to fetch pref img")
-            .resize(
-                self.size,
-                self.size,
-                image::imageops::FilterType::CatmullRom,
-            );
-
-        let pref_img = pref_img
-            .as_rgba8()
-            .expect("failed to parse pref image to rgb8");
+    // 都道府県の形の範囲だけを、その場で塗る。
+    pub fn overlay(&mut self, pref: &Pref, tint_color: &Rgb<u8>) -> Result<()> {
+        let mask = self.masks.get(pref, self.size)?;
+        let [r, g, b] = tint_color.0;
 
         let base_img = self
             .img
             .as_mut_rgba8()
             .expect("failed to parse color type as rgba8");
+        let row_len = base_img.width() as usize * 4;
+        let (x, width) = (mask.x as usize * 4, mask.width as usize * 4);
 
         base_img
-            .pixels_mut()
-            .zip(pref_img.pixels())
-            .par_bridge()
-            .for_each(|(base, pref)| {
-                let tinted_pixel = tint_pixel(pref, tint_color);
-                base.blend(&tinted_pixel);
+            .par_chunks_mut(row_len)
+            .skip(mask.y as usize)
+            .zip(mask.alpha.par_chunks(mask.width.max(1) as usize))
+            .for_each(|(row, alpha)| {
+                row[x..x + width]
+                    .chunks_mut(4)
+                    .zip(alpha)
+                    .filter(|(_, &a)| a > 0)
+                    .for_each(|(base, &a)| {
+                        Rgba::from_slice_mut(base).blend(&Rgba([r, g, b, a]));
+                    });
             });
 
-        let img = DynamicImage::ImageRgba8(base_img.to_owned());
+        Ok(())
+    }
 
-        self.img = img;
+    // 使う都道府県の形を、塗り始める前にまとめて読んでおく。
+    pub fn preload(&self, prefs: &[Pref]) -> Result<()> {
+        self.masks.preload(prefs, self.size)
     }
 
     pub fn get_img(&self) -> DynamicImage {
         self.img.clone()
     }
+
+    pub fn set_font(&mut self, font: FontArc) {
+        self.font = Some(font);
+    }
+
+    // 書き出すコマに文字を載せる。塗った結果には残さない。
+    pub fn draw_text(&self, img: &mut DynamicImage, text: &str, style: &TextStyle) -> Result<()> {
+        let font = self
+            .font
+            .as_ref()
+            .ok_or_else(|| anyhow!("no font is loaded"))?;
+        let img = img
+            .as_mut_rgba8()
+            .expect("failed to parse color type as rgba8");
+
+        draw_text(img, font, text, style)
+    }
+
+    pub fn draw_legend(
+        &self,
+        img: &mut DynamicImage,
+        rows: &[LegendRow],
+        options: &LegendOptions,
+    ) -> Result<()> {
+        let font = self
+            .font
+            .as_ref()
+            .ok_or_else(|| anyhow!("no font is loaded"))?;
+        let img = img
+            .as_mut_rgba8()
+            .expect("failed to parse color type as rgba8");
+
+        draw_legend(img, font, rows, options)
+    }
+
+    // この解像度での都道府県の形の情報。1度求めたものは使い回す。
+    pub fn geometry(&self, pref: &Pref) -> Result<Arc<PrefGeometry>> {
+        self.geometries.get(pref, self.size)
+    }
+
+    pub fn draw_labels(
+        &self,
+        img: &mut DynamicImage,
+        labels: &[Label],
+        style: &TextStyle,
+    ) -> Result<()> {
+        let font = self
+            .font
+            .as_ref()
+            .ok_or_else(|| anyhow!("no font is loaded"))?;
+        let img = img
+            .as_mut_rgba8()
+            .expect("failed to parse color type as rgba8");
+
+        draw_labels(img, font, labels, style)
+    }
+
+    // 都道府県の形から、ふちの光だけを描いた透明な画像を作る。
+    // 塗った結果には混ぜないので、次のコマでは消える。
+    pub fn glow(&mut self, pref: &Pref, highlight: &Highlight) -> Result<Arc<Glow>> {
+        let key = (pref.clone(), highlight.clone());
+        if let Some(glow) = self.glows.get(&key) {
+            return Ok(glow.clone());
+        }
+
+        let glow = Arc::new(self.make_glow(pref, highlight)?);
+        self.glows.insert(key, glow.clone());
+
+        Ok(glow)
+    }
+
+    fn make_glow(&self, pref: &Pref, highlight: &Highlight) -> Result<Glow> {
+        let mask = self.masks.get(pref, self.size)?;
+
+        // 光が届くのは形の範囲から光の広がりの分だけ外まで。
+        // 1px余分に取って、内側のふちまでの距離も地図全体で求めたときと同じにする。
+        let pad = highlight.width.max(1) + 1;
+        let (x0, y0) = (mask.x.saturating_sub(pad), mask.y.saturating_sub(pad));
+        let x1 = (mask.x + mask.width + pad).min(self.size);
+        let y1 = (mask.y + mask.height + pad).min(self.size);
+        let (w, h) = (x1.saturating_sub(x0), y1.saturating_sub(y0));
+
+        let inside = (y0..y1)
+            .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+            .map(|(x, y)| mask.get(x, y) > 127)
+            .collect::<Vec<_>>();
+        let outer = chamfer_distance(&inside, w as usize, h as usize);
+        let inner = chamfer_distance(
+            &inside.iter().map(|i| !i).collect::<Vec<_>>(),
+            w as usize,
+            h as usize,
+        );
+
+        let width = highlight.width.max(1) as f32;
+        let stroke = (width / 3.0).max(1.0);
+        let [r, g, b] = highlight.color.0;
+
+        let mut layer = RgbaImage::new(w, h);
+        layer
+            .pixels_mut()
+            .zip(outer.iter().zip(&inner))
+            .for_each(|(px, (&out, &inn))| {
+                let alpha = if inn > 0.0 {
+                    // 内側のふちは線としてはっきり描く。
+                    if inn <= stroke {
+                        1.0
+                    } else {
+                        0.0
+                    }
+                } else if out <= width {
+                    (1.0 - out / (width + 1.0)).powf(1.5)
+                } else {
+                    0.0
+                };
+                *px = Rgba([r, g, b, (alpha * 255.0).round() as u8]);
+            });
+
+        Ok(Glow {
+            x: x0,
+            y: y0,
+            layer,
+        })
+    }
 }
 
-fn tint_pixel(pixel: &Rgba<u8>, tint_color: &Rgb<u8>) -> Rgba<u8> {
-    let channels = pixel
-        .channels()
-        .iter()
-        .take(3)
-        .zip(tint_color.channels())
-        .map(|(&p, &t)| (p as f64 * (t as f64 / 255.0)).clamp(0.0, 255.0) as u8)
-        .collect::<Vec<_>>();
-
-    let [r, g, b] = channels.as_slice() else {
-        panic!("failed to parse rgb");
+// 光の届く範囲だけを重ねる。
+pub fn with_glow(img: &DynamicImage, glow: &Glow) -> DynamicImage {
+    let mut img = img.to_rgba8();
+    let row_len = img.width() as usize * 4;
+    let (x, width) = (glow.x as usize * 4, glow.layer.width() as usize * 4);
+
+    img.par_chunks_mut(row_len)
+        .skip(glow.y as usize)
+        .zip(glow.layer.par_chunks(width.max(4)))
+        .for_each(|(row, layer)| {
+            row[x..x + width]
+                .chunks_mut(4)
+                .zip(layer.chunks(4))
+                .for_each(|(base, g)| Rgba::from_slice_mut(base).blend(Rgba::from_slice(g)));
+        });
+
+    DynamicImage::ImageRgba8(img)
+}
+
+// 塗る前と塗った後の間のコマを作る。tは0.0(前)から1.0(後)まで。
+pub fn transition_img(
+    prev: &DynamicImage,
+    next: &DynamicImage,
+    t: f32,
+    transition: Transition,
+) -> DynamicImage {
+    let prev = prev.to_rgba8();
+    let next = next.to_rgba8();
+    let mut img = RgbaImage::new(next.width(), next.height());
+
+    // パルスは途中で白っぽく光らせる。塗り変わったピクセルだけ。
+    let glow = match transition {
+        Transition::Fade => 0.0,
+        Transition::Pulse => (t * std::f32::consts::PI).sin() * 0.6,
     };
-    let a = pixel.0[3];
 
-    Rgba::from([*r, *g, *b, a])
+    img.par_chunks_mut(4)
+        .zip(prev.par_chunks(4).zip(next.par_chunks(4)))
+        .for_each(|(out, (p, n))| {
+            let changed = p != n;
+            for c in 0..4 {
+                let mut v = p[c] as f32 + (n[c] as f32 - p[c] as f32) * t;
+                if changed && c < 3 {
+                    v += (255.0 - v) * glow;
+                }
+                out[c] = v.round().clamp(0.0, 255.0) as u8;
+            }
+        });
+
+    DynamicImage::ImageRgba8(img)
 }
@@ -0,0 +1,421 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::{Delay, ExtendedColorType, ImageEncoder};
+
+use super::sink::{create_output_file, output_path, FrameSink};
+use crate::{Frame, Timing};
+
+#[derive(Debug, Clone)]
+pub struct AnimationOptions {
+    pub timing: Timing,
+    // 何回再生するか。0なら無限ループ
+    pub loop_count: u16,
+}
+
+impl AnimationOptions {
+    // 1コマごとに丸めると合計がずれていくので、位置を丸めてから差をとる。
+    fn delay_ms(&self, frame: &Frame) -> u32 {
+        let timing = &self.timing;
+        (timing.to_ms(frame.start + frame.duration) - timing.to_ms(frame.start)) as u32
+    }
+
+    // GIFは10ms単位でしか持てないので、位置も10ms単位で丸めてから差をとる。
+    fn gif_delay_ms(&self, frame: &Frame) -> u32 {
+        let fps = self.timing.fps() as u64;
+        let to_cs = |position: u32| position as u64 * 100 / fps;
+        (to_cs(frame.start + frame.duration) - to_cs(frame.start)) as u32 * 10
+    }
+}
+
+pub struct GifSink {
+    encoder: GifEncoder<BufWriter<File>>,
+    path: PathBuf,
+    options: AnimationOptions,
+}
+
+impl GifSink {
+    pub fn create(out_dir: &Path, options: &AnimationOptions) -> Result<Self> {
+        let path = output_path(out_dir, Some("gif"));
+        let file = create_output_file(out_dir, &path)?;
+
+        let mut encoder = GifEncoder::new(BufWriter::new(file));
+        // GIFに書くのは最初の1回に足す回数なので、APNGやWebPと揃えるために1回引く。
+        encoder.set_repeat(match options.loop_count {
+            0 => Repeat::Infinite,
+            n => Repeat::Finite(n - 1),
+        })?;
+
+        Ok(Self {
+            encoder,
+            path,
+            options: options.clone(),
+        })
+    }
+}
+
+impl FrameSink for GifSink {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()> {
+        let delay = Delay::from_numer_denom_ms(self.options.gif_delay_ms(frame), 1);
+        self.encoder
+            .encode_frame(image::Frame::from_parts(frame.img.to_rgba8(), 0, 0, delay))?;
+
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<PathBuf> {
+        // トレーラーはGifEncoderのdropで書かれる。
+        let Self { encoder, path, .. } = *self;
+        drop(encoder);
+
+        Ok(path)
+    }
+}
+
+// acTLにコマ数が必要なので、圧縮済みのIDATだけ溜めておいてfinishでまとめて書く。
+pub struct ApngSink {
+    file: File,
+    path: PathBuf,
+    options: AnimationOptions,
+    ihdr: Option<Vec<u8>>,
+    // (IDAT, 表示するフレーム数)
+    frames: Vec<(Vec<u8>, u32)>,
+}
+
+impl ApngSink {
+    pub fn create(out_dir: &Path, options: &AnimationOptions) -> Result<Self> {
+        let path = output_path(out_dir, Some("png"));
+        let file = create_output_file(out_dir, &path)?;
+
+        Ok(Self {
+            file,
+            path,
+            options: options.clone(),
+            ihdr: None,
+            frames: Vec::new(),
+        })
+    }
+}
+
+impl FrameSink for ApngSink {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()> {
+        let img = frame.img.to_rgba8();
+        let mut png = Vec::new();
+        PngEncoder::new(&mut png).write_image(
+            img.as_raw(),
+            img.width(),
+            img.height(),
+            ExtendedColorType::Rgba8,
+        )?;
+
+        let mut idat = Vec::new();
+        for (kind, data) in png_chunks(&png)? {
+            match kind {
+                b"IHDR" if self.ihdr.is_none() => self.ihdr = Some(data.to_vec()),
+                b"IHDR" if self.ihdr.as_deref() != Some(data) => {
+                    bail!("all frames must have the same size")
+                }
+                b"IDAT" => idat.extend_from_slice(data),
+                _ => {}
+            }
+        }
+        self.frames.push((idat, frame.duration));
+
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<PathBuf> {
+        let Some(ihdr) = &self.ihdr else {
+            bail!("no frames to write");
+        };
+        let (width, height) = (&ihdr[0..4], &ihdr[4..8]);
+
+        let mut w = BufWriter::new(&self.file);
+        w.write_all(b"\x89PNG\r\n\x1a\n")?;
+        write_png_chunk(&mut w, b"IHDR", ihdr)?;
+
+        let mut actl = Vec::new();
+        actl.extend_from_slice(&(self.frames.len() as u32).to_be_bytes());
+        actl.extend_from_slice(&(self.options.loop_count as u32).to_be_bytes());
+        write_png_chunk(&mut w, b"acTL", &actl)?;
+
+        // APNGは分数で持てるので、フレーム数/fpsをそのまま入れる。
+        let fps = self.options.timing.fps().min(u16::MAX as u32) as u16;
+        let mut seq = 0u32;
+        for (idx, (idat, duration)) in self.frames.iter().enumerate() {
+            let mut fctl = Vec::new();
+            fctl.extend_from_slice(&seq.to_be_bytes());
+            fctl.extend_from_slice(width);
+            fctl.extend_from_slice(height);
+            fctl.extend_from_slice(&0u32.to_be_bytes());
+            fctl.extend_from_slice(&0u32.to_be_bytes());
+            fctl.extend_from_slice(&((*duration).min(u16::MAX as u32) as u16).to_be_bytes());
+            fctl.extend_from_slice(&fps.to_be_bytes());
+            // dispose_op: none, blend_op: source
+            fctl.extend_from_slice(&[0, 0]);
+            write_png_chunk(&mut w, b"fcTL", &fctl)?;
+            seq += 1;
+
+            if idx == 0 {
+                write_png_chunk(&mut w, b"IDAT", idat)?;
+            } else {
+                let mut fdat = Vec::with_capacity(idat.len() + 4);
+                fdat.extend_from_slice(&seq.to_be_bytes());
+                fdat.extend_from_slice(idat);
+                write_png_chunk(&mut w, b"fdAT", &fdat)?;
+                seq += 1;
+            }
+        }
+
+        write_png_chunk(&mut w, b"IEND", &[])?;
+        w.flush()?;
+        drop(w);
+
+        Ok(self.path)
+    }
+}
+
+// こちらもRIFFの先頭にサイズが必要なので、finishでまとめて書く。
+pub struct WebpSink {
+    file: File,
+    path: PathBuf,
+    options: AnimationOptions,
+    size: Option<(u32, u32)>,
+    // (ANMFの中身, 表示するミリ秒)
+    frames: Vec<(Vec<u8>, u32)>,
+}
+
+impl WebpSink {
+    pub fn create(out_dir: &Path, options: &AnimationOptions) -> Result<Self> {
+        let path = output_path(out_dir, Some("webp"));
+        let file = create_output_file(out_dir, &path)?;
+
+        Ok(Self {
+            file,
+            path,
+            options: options.clone(),
+            size: None,
+            frames: Vec::new(),
+        })
+    }
+}
+
+impl FrameSink for WebpSink {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()> {
+        let img = frame.img.to_rgba8();
+        let size = (img.width(), img.height());
+        match self.size {
+            None => self.size = Some(size),
+            Some(s) if s != size => bail!("all frames must have the same size"),
+            _ => {}
+        }
+
+        let mut webp = Vec::new();
+        WebPEncoder::new_lossless(&mut webp).write_image(
+            img.as_raw(),
+            img.width(),
+            img.height(),
+            ExtendedColorType::Rgba8,
+        )?;
+
+        // VP8X以外(ALPH, VP8, VP8L)をそのままANMFの中身にする。
+        let mut data = Vec::new();
+        for (kind, chunk) in riff_chunks(&webp)? {
+            if kind != b"VP8X" {
+                write_riff_chunk(&mut data, kind, chunk)?;
+            }
+        }
+        self.frames.push((data, self.options.delay_ms(frame)));
+
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<PathBuf> {
+        let Some((width, height)) = self.size else {
+            bail!("no frames to write");
+        };
+
+        let mut body = Vec::new();
+        body.extend_from_slice(b"WEBP");
+
+        let mut vp8x = vec![0x10 | 0x02, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(width - 1));
+        vp8x.extend_from_slice(&u24(height - 1));
+        write_riff_chunk(&mut body, b"VP8X", &vp8x)?;
+
+        let mut anim = Vec::new();
+        anim.extend_from_slice(&[0, 0, 0, 0]);
+        anim.extend_from_slice(&self.options.loop_count.to_le_bytes());
+        write_riff_chunk(&mut body, b"ANIM", &anim)?;
+
+        for (data, delay_ms) in &self.frames {
+            let mut anmf = Vec::with_capacity(data.len() + 16);
+            anmf.extend_from_slice(&u24(0));
+            anmf.extend_from_slice(&u24(0));
+            anmf.extend_from_slice(&u24(width - 1));
+            anmf.extend_from_slice(&u24(height - 1));
+            anmf.extend_from_slice(&u24((*delay_ms).min(0xff_ffff)));
+            // 重ね合わせずに上書き、破棄もしない。
+            anmf.push(0b10);
+            anmf.extend_from_slice(data);
+            write_riff_chunk(&mut body, b"ANMF", &anmf)?;
+        }
+
+        let mut w = BufWriter::new(&self.file);
+        w.write_all(b"RIFF")?;
+        w.write_all(&(body.len() as u32).to_le_bytes())?;
+        w.write_all(&body)?;
+        w.flush()?;
+        drop(w);
+
+        Ok(self.path)
+    }
+}
+
+fn png_chunks(png: &[u8]) -> Result<Vec<(&[u8; 4], &[u8])>> {
+    let mut chunks = Vec::new();
+    let mut rest = png.get(8..).unwrap_or_default();
+
+    while rest.len() >= 12 {
+        let len = u32::from_be_bytes(rest[0..4].try_into()?) as usize;
+        let kind: &[u8; 4] = rest[4..8].try_into()?;
+        let Some(data) = rest.get(8..8 + len) else {
+            bail!("broken png chunk");
+        };
+        chunks.push((kind, data));
+        rest = rest.get(12 + len..).unwrap_or_default();
+    }
+
+    Ok(chunks)
+}
+
+fn write_png_chunk(w: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    let mut crc = crc32fast::Hasher::new();
+    crc.update(kind);
+    crc.update(data);
+
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    w.write_all(&crc.finalize().to_be_bytes())?;
+
+    Ok(())
+}
+
+fn riff_chunks(webp: &[u8]) -> Result<Vec<(&[u8; 4], &[u8])>> {
+    let mut chunks = Vec::new();
+    let mut rest = webp.get(12..).unwrap_or_default();
+
+    while rest.len() >= 8 {
+        let kind: &[u8; 4] = rest[0..4].try_into()?;
+        let len = u32::from_le_bytes(rest[4..8].try_into()?) as usize;
+        let Some(data) = rest.get(8..8 + len) else {
+            bail!("broken webp chunk");
+        };
+        chunks.push((kind, data));
+        rest = rest.get(8 + len + len % 2..).unwrap_or_default();
+    }
+
+    Ok(chunks)
+}
+
+fn write_riff_chunk(w: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    w.write_all(kind)?;
+    w.write_all(&(data.len() as u32).to_le_bytes())?;
+    w.write_all(data)?;
+    if data.len() % 2 == 1 {
+        w.write_all(&[0])?;
+    }
+
+    Ok(())
+}
+
+fn u24(n: u32) -> [u8; 3] {
+    let [a, b, c, _] = n.to_le_bytes();
+    [a, b, c]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use image::codecs::gif::GifDecoder;
+    use image::{AnimationDecoder, DynamicImage};
+
+    use super::*;
+
+    fn frame(start: u32, duration: u32) -> Frame {
+        Frame {
+            index: start as usize,
+            start,
+            duration,
+            pref: None,
+            visit_count: 0,
+            tint_color: None,
+            transition: false,
+            img: DynamicImage::new_rgba8(2, 2),
+        }
+    }
+
+    fn write_gif(name: &str, options: &AnimationOptions, frames: &[Frame]) -> Result<Vec<u8>> {
+        let dir = std::env::temp_dir().join(format!("oyassan-{}-{}", name, std::process::id()));
+        let mut sink: Box<dyn FrameSink> = Box::new(GifSink::create(&dir, options)?);
+        for frame in frames {
+            sink.add_frame(frame)?;
+        }
+        let gif = fs::read(sink.finish()?)?;
+        fs::remove_dir_all(&dir)?;
+
+        Ok(gif)
+    }
+
+    #[test]
+    fn gif_delays_do_not_drift() -> Result<()> {
+        let options = AnimationOptions {
+            timing: Timing {
+                fps: 30,
+                ..Default::default()
+            },
+            loop_count: 0,
+        };
+        let gif = write_gif(
+            "gif-delay",
+            &options,
+            &[frame(0, 1), frame(1, 1), frame(2, 1)],
+        )?;
+
+        let delays = GifDecoder::new(std::io::Cursor::new(gif))?
+            .into_frames()
+            .map(|frame| Ok(frame?.delay().numer_denom_ms()))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(delays, [(30, 1), (30, 1), (40, 1)]);
+
+        Ok(())
+    }
+
+    // NETSCAPE2.0拡張の繰り返し回数。無ければ1回だけ再生される。
+    fn gif_repeat(gif: &[u8]) -> Option<u16> {
+        let at = gif.windows(11).position(|w| w == b"NETSCAPE2.0")? + 11;
+        Some(u16::from_le_bytes([gif[at + 2], gif[at + 3]]))
+    }
+
+    #[test]
+    fn gif_loop_count_is_total_plays() -> Result<()> {
+        let frames = [frame(0, 1), frame(1, 1)];
+        for (loop_count, repeat) in [(0, Some(0)), (1, None), (3, Some(2))] {
+            let options = AnimationOptions {
+                timing: Timing::default(),
+                loop_count,
+            };
+            let gif = write_gif("gif-loop", &options, &frames)?;
+            assert_eq!(gif_repeat(&gif), repeat, "loop_count {}", loop_count);
+        }
+
+        Ok(())
+    }
+}
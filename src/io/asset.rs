@@ -0,0 +1,254 @@
+use std::borrow::Cow;
+use std::env;
+use std::fmt::Display;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use image::DynamicImage;
+
+use crate::Pref;
+
+const APP_NAME: &str = "oyassan";
+const DATA_DIR_ENV: &str = "OYASSAN_DATA_DIR";
+const CONFIG_ENV: &str = "OYASSAN_CONFIG";
+const CONFIG_FILE: &str = "config.json";
+
+const EMBEDDED_DICT: &[u8] = include_bytes!("../../data/pref.csv");
+const EMBEDDED_NOTICE_SOUND: &[u8] = include_bytes!("../../data/notice.mp3");
+
+macro_rules! embed_maps {
+    ($($key:literal),* $(,)?) => {
+        fn embedded_map(key: &str) -> Option<&'static [u8]> {
+            match key {
+                $($key => Some(include_bytes!(concat!("../../data/maps/", $key, ".png"))),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+embed_maps!(
+    "full",
+    "aichi",
+    "akita",
+    "aomori",
+    "chiba",
+    "ehime",
+    "fukui",
+    "fukuoka",
+    "fukushima",
+    "gifu",
+    "gumma",
+    "hiroshima",
+    "okayama",
+    "hokkai",
+    "hyogo",
+    "ibaraki",
+    "ishikawa",
+    "iwate",
+    "kagawa",
+    "kagoshima",
+    "kanagawa",
+    "kochi",
+    "kumamoto",
+    "kyoto",
+    "mie",
+    "miyagi",
+    "miyazaki",
+    "nagano",
+    "nagasaki",
+    "nara",
+    "niigata",
+    "oita",
+    "okinawa",
+    "osaka",
+    "saga",
+    "saitama",
+    "shiga",
+    "shimane",
+    "shizuoka",
+    "tochigi",
+    "tokushima",
+    "tokyo",
+    "tottori",
+    "toyama",
+    "wakayama",
+    "yamagata",
+    "yamaguchi",
+    "yamanashi",
+);
+
+#[derive(Debug, Clone)]
+pub struct AssetNotFound {
+    pub what: String,
+    pub searched: Vec<PathBuf>,
+    // 場所を教えるための引数と環境変数
+    pub flag: &'static str,
+    pub env: &'static str,
+}
+
+impl Display for AssetNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "おやっさん「{}が見つからねぇ。探したのはここだ。」",
+            self.what
+        )?;
+        for path in &self.searched {
+            writeln!(f, "  - {}", path.display())?;
+        }
+        write!(f, "`{}`か`{}`で場所を教えてくれ。", self.flag, self.env)
+    }
+}
+
+impl std::error::Error for AssetNotFound {}
+
+// data_dirにあるファイルを優先して、なければ実行ファイルに埋め込んだものを使う。
+#[derive(Debug, Clone)]
+pub struct Assets {
+    data_dir: Option<PathBuf>,
+    config_path: Option<PathBuf>,
+    config_save_path: PathBuf,
+}
+
+impl Default for Assets {
+    fn default() -> Self {
+        Self::embedded()
+    }
+}
+
+impl Assets {
+    pub fn embedded() -> Self {
+        Self {
+            data_dir: None,
+            config_path: None,
+            config_save_path: PathBuf::from(CONFIG_FILE),
+        }
+    }
+
+    // 優先順位: 引数 > 環境変数 > 実行ファイルの隣 > XDGのデータディレクトリ > カレントディレクトリ
+    pub fn locate(data_dir: Option<PathBuf>, config_path: Option<PathBuf>) -> Result<Self> {
+        let data_dir = locate_data_dir(data_dir)?;
+
+        let config_candidates = match config_path.or_else(|| env_path(CONFIG_ENV)) {
+            Some(explicit) if explicit.is_file() => vec![explicit],
+            Some(explicit) => {
+                return Err(AssetNotFound {
+                    what: String::from("設定ファイル"),
+                    searched: vec![explicit],
+                    flag: "--config",
+                    env: CONFIG_ENV,
+                }
+                .into());
+            }
+            None => dirs::config_dir()
+                .map(|dir| dir.join(APP_NAME).join(CONFIG_FILE))
+                .into_iter()
+                .chain(data_dir.as_ref().map(|dir| dir.join(CONFIG_FILE)))
+                .collect(),
+        };
+        let config_path = config_candidates
+            .iter()
+            .find(|path| path.is_file())
+            .cloned();
+        let config_save_path = config_path
+            .clone()
+            .or_else(|| config_candidates.first().cloned())
+            .unwrap_or_else(|| PathBuf::from(CONFIG_FILE));
+
+        Ok(Self {
+            data_dir,
+            config_path,
+            config_save_path,
+        })
+    }
+
+    pub fn data_dir(&self) -> Option<&Path> {
+        self.data_dir.as_deref()
+    }
+
+    pub fn config_path(&self) -> Option<&Path> {
+        self.config_path.as_deref()
+    }
+
+    pub fn config_save_path(&self) -> &Path {
+        &self.config_save_path
+    }
+
+    pub fn dict(&self) -> Result<String> {
+        let raw = self.read(Path::new("pref.csv"), Some(EMBEDDED_DICT))?;
+        Ok(String::from_utf8(raw.into_owned())?)
+    }
+
+    pub fn base_img(&self) -> Result<DynamicImage> {
+        self.map("full")
+    }
+
+    pub fn pref_img(&self, pref: &Pref) -> Result<DynamicImage> {
+        self.map(&pref.as_key())
+    }
+
+    pub fn notice_sound(&self) -> Result<Cow<'static, [u8]>> {
+        self.read(Path::new("notice.mp3"), Some(EMBEDDED_NOTICE_SOUND))
+    }
+
+    fn map(&self, key: &str) -> Result<DynamicImage> {
+        let path = Path::new("maps").join(format!("{}.png", key));
+        let raw = self.read(&path, embedded_map(key))?;
+
+        Ok(image::load_from_memory(&raw)?)
+    }
+
+    fn read(&self, path: &Path, embedded: Option<&'static [u8]>) -> Result<Cow<'static, [u8]>> {
+        if let Some(dir) = &self.data_dir {
+            let path = dir.join(path);
+            if path.is_file() {
+                return Ok(Cow::Owned(fs::read(path)?));
+            }
+        }
+
+        embedded.map(Cow::Borrowed).ok_or_else(|| {
+            AssetNotFound {
+                what: path.display().to_string(),
+                searched: self.data_dir.iter().map(|dir| dir.join(path)).collect(),
+                flag: "--data-dir",
+                env: DATA_DIR_ENV,
+            }
+            .into()
+        })
+    }
+}
+
+fn locate_data_dir(explicit: Option<PathBuf>) -> Result<Option<PathBuf>> {
+    if let Some(explicit) = explicit.or_else(|| env_path(DATA_DIR_ENV)) {
+        if explicit.is_dir() {
+            return Ok(Some(explicit));
+        }
+
+        return Err(AssetNotFound {
+            what: String::from("dataフォルダ"),
+            searched: vec![explicit],
+            flag: "--data-dir",
+            env: DATA_DIR_ENV,
+        }
+        .into());
+    }
+
+    let next_to_exe = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join("data")));
+    let xdg_data = dirs::data_dir().map(|dir| dir.join(APP_NAME));
+
+    Ok(next_to_exe
+        .into_iter()
+        .chain(xdg_data)
+        .chain([PathBuf::from("./data")])
+        .find(|dir| dir.is_dir()))
+}
+
+fn env_path(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|v| !v.is_empty())
+        .map(PathBuf::from)
+}
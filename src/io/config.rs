@@ -1,20 +1,34 @@
 use std::collections::HashSet;
 use std::ffi::OsString;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::{BufReader, Write};
 use std::path::{Path, PathBuf};
 use std::process::exit;
 
-use anyhow::Result;
+use anyhow::{anyhow, bail, Result};
 
 use promptuity::prompts::{Confirm, Input, MultiSelect, MultiSelectOption, Select, SelectOption};
 use promptuity::themes::FancyTheme;
 use promptuity::{Promptuity, Term};
 use serde::{Deserialize, Serialize};
 
-const CONFIG_PATH: &str = "./data/config.json";
-
-#[derive(Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
+use super::anim::{AnimationOptions, ApngSink, GifSink, WebpSink};
+use super::asset::Assets;
+use super::delta::{DeltaOptions, DeltaSink};
+use super::dir::DirSink;
+use super::edl::{EditListOptions, EditListWriter};
+use super::exo::{ExoOptions, ExoTemplate, ExoWriter};
+use super::json::parse_color;
+use super::layered::{layered_config, value_enum_config_value, Layer, LayeredConfig};
+use super::manifest::{input_sha256, ManifestWriter};
+use super::sink::{FrameSink, RecordingSink};
+use super::subtitle::{SubtitleOptions, SubtitleWriter};
+use super::tar::TarSink;
+use super::y4m::Y4mSink;
+use super::zip::{ZipBuilder, ZipOptions};
+use crate::{Highlight, LabelOptions, LegendOptions, TextOptions, TextStyle, Timing};
+
+#[derive(ValueEnum, Clone, Debug, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Issue {
     InvalidColor,
     EmptyColor,
@@ -60,28 +74,441 @@ impl Display for Resolution {
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Config {
-    pub input_path: PathBuf,
-    pub play_notification_sound: bool,
-    pub resolution: Resolution,
-    pub ignore_issues: HashSet<Issue>,
+#[derive(ValueEnum, Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutputFormat {
+    #[default]
+    Zip,
+    Dir,
+    Tar,
+    Gif,
+    Apng,
+    Webp,
+    Y4m,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            input_path: PathBuf::default(),
-            play_notification_sound: true,
-            resolution: Resolution::default(),
-            ignore_issues: HashSet::default(),
-        }
+impl Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Zip => "zip",
+            Self::Dir => "dir",
+            Self::Tar => "tar",
+            Self::Gif => "gif",
+            Self::Apng => "apng",
+            Self::Webp => "webp",
+            Self::Y4m => "y4m",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+// zipに入れるときの圧縮方法。PNGはもともと圧縮されているので、storedでもあまり変わらない。
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Compression {
+    Stored,
+    Deflate,
+    #[default]
+    Bzip2,
+    Zstd,
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Stored => "stored",
+            Self::Deflate => "deflate",
+            Self::Bzip2 => "bzip2",
+            Self::Zstd => "zstd",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Transition {
+    // 前の色から徐々に塗る
+    #[default]
+    Fade,
+    // 塗りながら一瞬明るく光らせる
+    Pulse,
+}
+
+impl Display for Transition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Fade => "fade",
+            Self::Pulse => "pulse",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextPosition {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Display for TextPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::TopLeft => "top-left",
+            Self::Top => "top",
+            Self::TopRight => "top-right",
+            Self::Left => "left",
+            Self::Center => "center",
+            Self::Right => "right",
+            Self::BottomLeft => "bottom-left",
+            Self::Bottom => "bottom",
+            Self::BottomRight => "bottom-right",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+// 地図に載せる都道府県名の表記
+#[derive(ValueEnum, Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LabelName {
+    #[default]
+    Kanji,
+    Kana,
+    Romaji,
+}
+
+impl Display for LabelName {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Kanji => "kanji",
+            Self::Kana => "kana",
+            Self::Romaji => "romaji",
+        };
+
+        write!(f, "{}", s)
     }
 }
 
+value_enum_config_value!(
+    Issue,
+    Resolution,
+    OutputFormat,
+    Compression,
+    Transition,
+    TextPosition,
+    LabelName
+);
+
+layered_config! {
+    input_path: PathBuf = PathBuf::default(),
+    play_notification_sound: bool = true,
+    resolution: Resolution = Resolution::default(),
+    ignore_issues: HashSet<Issue> = HashSet::default(),
+    out_dir: PathBuf = PathBuf::from("./out"),
+    output: OutputFormat = OutputFormat::default(),
+    encode_workers: Option<usize> = None,
+    compression: Compression = Compression::default(),
+    optimize_png: bool = false,
+    delta: bool = false,
+    delta_keyframe_interval: u32 = 30,
+    fps: u32 = 10,
+    entry_secs: f64 = 0.1,
+    hold_last_secs: f64 = 0.0,
+    intro_secs: f64 = 0.0,
+    transition: Transition = Transition::default(),
+    transition_frames: u32 = 0,
+    loop_count: u16 = 0,
+    background: String = String::from("#000000"),
+    stdout: bool = false,
+    exo: bool = false,
+    exo_layer: u32 = 1,
+    exo_x: f64 = 0.0,
+    exo_y: f64 = 0.0,
+    exo_template: Option<PathBuf> = None,
+    subtitles: bool = false,
+    caption: String = String::from("{number}件目: {pref}"),
+    edl: bool = false,
+    fcpxml: bool = false,
+    highlight: bool = false,
+    highlight_color: String = String::from("#ffffff"),
+    highlight_width: u32 = 4,
+    font: Option<PathBuf> = None,
+    text_caption: Option<String> = None,
+    text_title: Option<String> = None,
+    text_footer: Option<String> = None,
+    text_caption_position: TextPosition = TextPosition::Bottom,
+    text_title_position: TextPosition = TextPosition::Top,
+    text_footer_position: TextPosition = TextPosition::BottomRight,
+    text_caption_size: f64 = 0.06,
+    text_title_size: f64 = 0.07,
+    text_footer_size: f64 = 0.035,
+    text_color: String = String::from("#ffffff"),
+    text_outline_color: String = String::from("#000000"),
+    text_outline_width: u32 = 2,
+    legend: bool = false,
+    legend_last_only: bool = false,
+    // 日本海側は空いているので、上下の文字と重ならない左の真ん中に置く。
+    legend_position: TextPosition = TextPosition::Left,
+    legend_size: f64 = 0.035,
+    legend_label: String = String::from("{visits}回目: {count}"),
+    labels: bool = false,
+    label_name: LabelName = LabelName::default(),
+    label_text: String = String::from("{name}"),
+    label_size: f64 = 0.025,
+}
+
 impl Config {
-    pub fn wizard(need_advanced: bool, only_reqired: bool) -> Result<Self> {
-        let res = wizard_(need_advanced, only_reqired);
+    pub fn from_file(assets: &Assets) -> Result<Self> {
+        let mut layered = LayeredConfig::default();
+        layered.apply(Layer::File, PartialConfig::from_file(assets)?);
+
+        Ok(layered.config)
+    }
+
+    pub fn exist_config_file(assets: &Assets) -> bool {
+        assets.config_path().is_some()
+    }
+
+    pub fn timing(&self) -> Timing {
+        Timing::from_config(self)
+    }
+
+    pub fn animation_options(&self) -> AnimationOptions {
+        AnimationOptions {
+            timing: self.timing(),
+            loop_count: self.loop_count,
+        }
+    }
+
+    pub fn zip_options(&self) -> ZipOptions {
+        ZipOptions {
+            compression: self.compression,
+            optimize_png: self.optimize_png,
+            workers: self.encode_workers,
+        }
+    }
+
+    pub fn delta_options(&self) -> DeltaOptions {
+        DeltaOptions {
+            keyframe_interval: self.delta_keyframe_interval,
+        }
+    }
+
+    pub fn exo_options(&self) -> ExoOptions {
+        ExoOptions {
+            timing: self.timing(),
+            layer: self.exo_layer,
+            x: self.exo_x,
+            y: self.exo_y,
+        }
+    }
+
+    pub fn subtitle_options(&self) -> SubtitleOptions {
+        SubtitleOptions {
+            timing: self.timing(),
+            caption: self.caption.clone(),
+        }
+    }
+
+    pub fn edit_list_options(&self) -> EditListOptions {
+        EditListOptions {
+            timing: self.timing(),
+            caption: self.caption.clone(),
+            edl: self.edl,
+            fcpxml: self.fcpxml,
+        }
+    }
+
+    pub fn highlight(&self) -> Result<Option<Highlight>> {
+        if !self.highlight {
+            return Ok(None);
+        }
+        let color = parse_color(self.highlight_color.clone())
+            .map_err(|c| anyhow!("`{}` is not a valid highlight color", c))?;
+
+        Ok(Some(Highlight {
+            color,
+            width: self.highlight_width,
+        }))
+    }
+
+    fn text_style(&self, position: TextPosition, size: f64) -> Result<TextStyle> {
+        let color = parse_color(self.text_color.clone())
+            .map_err(|c| anyhow!("`{}` is not a valid text color", c))?;
+        let outline_color = parse_color(self.text_outline_color.clone())
+            .map_err(|c| anyhow!("`{}` is not a valid text outline color", c))?;
+
+        Ok(TextStyle {
+            position,
+            size,
+            color,
+            outline_color,
+            outline_width: self.text_outline_width,
+        })
+    }
+
+    pub fn text_options(&self) -> Result<Option<TextOptions>> {
+        if self.text_caption.is_none() && self.text_title.is_none() && self.text_footer.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(TextOptions {
+            caption: self.text_caption.clone(),
+            title: self.text_title.clone(),
+            footer: self.text_footer.clone(),
+            caption_style: self.text_style(self.text_caption_position, self.text_caption_size)?,
+            title_style: self.text_style(self.text_title_position, self.text_title_size)?,
+            footer_style: self.text_style(self.text_footer_position, self.text_footer_size)?,
+        }))
+    }
+
+    pub fn legend_options(&self) -> Result<Option<LegendOptions>> {
+        if !self.legend {
+            return Ok(None);
+        }
+
+        Ok(Some(LegendOptions {
+            label: self.legend_label.clone(),
+            last_only: self.legend_last_only,
+            style: self.text_style(self.legend_position, self.legend_size)?,
+        }))
+    }
+
+    pub fn label_options(&self) -> Result<Option<LabelOptions>> {
+        if !self.labels {
+            return Ok(None);
+        }
+
+        Ok(Some(LabelOptions {
+            name: self.label_name,
+            text: self.label_text.clone(),
+            style: self.text_style(TextPosition::Center, self.label_size)?,
+        }))
+    }
+
+    pub fn create_sink(&self) -> Result<Box<dyn FrameSink>> {
+        if self.stdout && self.output != OutputFormat::Y4m {
+            bail!(
+                "stdout is only supported for y4m output, not {}",
+                self.output
+            );
+        }
+        if self.stdout && self.subtitles {
+            bail!("subtitles cannot be written next to stdout");
+        }
+
+        let is_sequence = matches!(
+            self.output,
+            OutputFormat::Zip | OutputFormat::Dir | OutputFormat::Tar
+        );
+        if self.delta {
+            if !is_sequence {
+                bail!(
+                    "delta frames are only supported for zip, dir and tar output, not {}",
+                    self.output
+                );
+            }
+            // exoや編集リストは丸ごとの連番画像を読ませるものなので、差分とは一緒に使えない。
+            if self.exo || self.exo_template.is_some() || self.edl || self.fcpxml {
+                bail!("delta frames cannot be used with exo, edl or fcpxml");
+            }
+            if self.delta_keyframe_interval == 0 {
+                bail!("delta_keyframe_interval must be at least 1");
+            }
+        }
+        let exo = self.exo || self.exo_template.is_some();
+        if exo && !is_sequence {
+            bail!(
+                "exo is only supported for zip, dir and tar output, not {}",
+                self.output
+            );
+        }
+        if (self.edl || self.fcpxml) && !is_sequence {
+            bail!(
+                "edl and fcpxml are only supported for zip, dir and tar output, not {}",
+                self.output
+            );
+        }
+
+        // 出力先を作ってから失敗すると空のファイルが残るので、読めるものは先に読んでおく。
+        let exo_template = self
+            .exo_template
+            .as_deref()
+            .map(ExoTemplate::load)
+            .transpose()?;
+        // 標準出力に流しているときは、置く場所がない。
+        let input_sha256 = (!self.stdout)
+            .then(|| input_sha256(&self.input_path))
+            .transpose()?;
+
+        let out_dir = self.out_dir.as_path();
+        let mut sink: Box<dyn FrameSink> = match self.output {
+            OutputFormat::Zip => Box::new(ZipBuilder::create(out_dir, &self.zip_options())?),
+            OutputFormat::Dir => Box::new(DirSink::create(out_dir, self.optimize_png)?),
+            OutputFormat::Tar => Box::new(TarSink::create(out_dir, self.optimize_png)?),
+            OutputFormat::Gif => Box::new(GifSink::create(out_dir, &self.animation_options())?),
+            OutputFormat::Apng => Box::new(ApngSink::create(out_dir, &self.animation_options())?),
+            OutputFormat::Webp => Box::new(WebpSink::create(out_dir, &self.animation_options())?),
+            OutputFormat::Y4m => {
+                let background = parse_color(self.background.clone())
+                    .map_err(|c| anyhow!("`{}` is not a valid background color", c))?;
+
+                if self.stdout {
+                    Box::new(Y4mSink::stdout(self.timing().fps(), background))
+                } else {
+                    Box::new(Y4mSink::create(out_dir, self.timing().fps(), background)?)
+                }
+            }
+        };
+
+        if self.delta {
+            sink = Box::new(DeltaSink::new(sink, &self.delta_options()));
+        }
+        if exo {
+            sink = Box::new(RecordingSink::new(
+                sink,
+                ExoWriter::new(&self.exo_options(), exo_template),
+            ));
+        }
+        if self.edl || self.fcpxml {
+            sink = Box::new(RecordingSink::new(
+                sink,
+                EditListWriter::new(&self.edit_list_options()),
+            ));
+        }
+        if self.subtitles {
+            sink = Box::new(RecordingSink::new(
+                sink,
+                SubtitleWriter::new(&self.subtitle_options()),
+            ));
+        }
+        if let Some(input_sha256) = input_sha256 {
+            sink = Box::new(RecordingSink::new(
+                sink,
+                ManifestWriter::new(self, input_sha256),
+            ));
+        }
+
+        Ok(sink)
+    }
+}
+
+impl PartialConfig {
+    pub fn wizard(
+        assets: &Assets,
+        need_advanced: bool,
+        only_reqired: bool,
+        input_path: Option<PathBuf>,
+    ) -> Result<Self> {
+        let res = wizard_(assets, need_advanced, only_reqired, input_path);
         if let Err(e) = &res {
             let promptuity_error = e.downcast_ref();
 
@@ -93,28 +520,55 @@ impl Config {
         res
     }
 
-    pub fn from_file() -> Result<Self> {
-        let reader = BufReader::new(File::open(CONFIG_PATH)?);
+    pub fn from_file(assets: &Assets) -> Result<Self> {
+        let path = assets.config_path().unwrap_or(assets.config_save_path());
+        let reader = BufReader::new(File::open(path)?);
         Ok(serde_json::from_reader(reader)?)
     }
 
-    pub fn exist_config_file() -> bool {
-        Path::new(CONFIG_PATH).exists()
+    // 保存してあった他の設定は残して、今回の分だけ書き換える。
+    pub fn save(&self, assets: &Assets) -> Result<()> {
+        let saved = match assets.config_path() {
+            Some(_) => Self::from_file(assets)?,
+            None => Self::default(),
+        };
+
+        let path = assets.config_save_path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = File::create(path)?;
+        let json = serde_json::to_string(&saved.merge(self.clone()))?;
+
+        file.write_all(json.as_bytes())?;
+
+        Ok(())
     }
 }
 
-fn wizard_(need_advanced: bool, only_reqired: bool) -> Result<Config> {
+fn wizard_(
+    assets: &Assets,
+    need_advanced: bool,
+    only_reqired: bool,
+    input_path: Option<PathBuf>,
+) -> Result<PartialConfig> {
     let mut term = Term::default();
     let mut theme = FancyTheme::default();
     let mut p = Promptuity::new(&mut term, &mut theme);
 
-    let mut config = Config::default();
+    let mut config = PartialConfig::default();
 
     p.term().clear()?;
 
     p.with_intro("おやっさんの作業場").begin()?;
 
-    config.input_path = {
+    config.input_path = Some(if let Some(input_path) = input_path {
+        p.info(format!(
+            "おやっさん「JSONファイルは`{}`だな。」",
+            input_path.display()
+        ))?;
+        input_path
+    } else {
         let raw_path = p
             .prompt(
                 Input::new("おやっさん「JSONファイルの場所を教えてくれ。」").with_validator(
@@ -137,7 +591,7 @@ fn wizard_(need_advanced: bool, only_reqired: bool) -> Result<Config> {
             .expect("failed to ask json.");
 
         PathBuf::from(raw_path)
-    };
+    });
 
     if only_reqired {
         p.with_outro("おし、じゃあやっていくか。").finish()?;
@@ -146,35 +600,37 @@ fn wizard_(need_advanced: bool, only_reqired: bool) -> Result<Config> {
 
     if need_advanced {
         p.info("おやっさん「上級者向け設定をしていくぞ。」")?;
-        config.resolution = p.prompt(
-            Select::new(
-                "おやっさん「画質はどうしたいんだ？高いほど時間はかかるぞ。」",
-                vec![
-                    SelectOption::new(
-                        format!("めっちゃ高い ({0:}x{0:})", Resolution::Ultra.as_size()),
-                        Resolution::Low,
-                    ),
-                    SelectOption::new(
-                        format!("高め ({0:}x{0:})", Resolution::High.as_size()),
-                        Resolution::Low,
-                    )
-                    .with_hint("デフォルト設定はこれだな。"),
-                    SelectOption::new(
-                        format!("中くらい ({0:}x{0:})", Resolution::Mid.as_size()),
-                        Resolution::Low,
-                    ),
-                    SelectOption::new(
-                        format!("低め ({0:}x{0:})", Resolution::Low.as_size()),
-                        Resolution::Low,
-                    ),
-                ],
-            )
-            .with_hint("エンターキーかスペースキーで決定できるぞ。"),
-        )?;
-
-        config.play_notification_sound = p.prompt(
+        config.resolution = Some(
+            p.prompt(
+                Select::new(
+                    "おやっさん「画質はどうしたいんだ？高いほど時間はかかるぞ。」",
+                    vec![
+                        SelectOption::new(
+                            format!("めっちゃ高い ({0:}x{0:})", Resolution::Ultra.as_size()),
+                            Resolution::Low,
+                        ),
+                        SelectOption::new(
+                            format!("高め ({0:}x{0:})", Resolution::High.as_size()),
+                            Resolution::Low,
+                        )
+                        .with_hint("デフォルト設定はこれだな。"),
+                        SelectOption::new(
+                            format!("中くらい ({0:}x{0:})", Resolution::Mid.as_size()),
+                            Resolution::Low,
+                        ),
+                        SelectOption::new(
+                            format!("低め ({0:}x{0:})", Resolution::Low.as_size()),
+                            Resolution::Low,
+                        ),
+                    ],
+                )
+                .with_hint("エンターキーかスペースキーで決定できるぞ。"),
+            )?,
+        );
+
+        config.play_notification_sound = Some(p.prompt(
             Confirm::new("おやっさん「作り終わったときに音を鳴らすか？」").with_default(true),
-        )?;
+        )?);
 
         let ignore_issues = p.prompt(
             MultiSelect::new(
@@ -189,10 +645,12 @@ fn wizard_(need_advanced: bool, only_reqired: bool) -> Result<Config> {
             .with_hint("スペースキーで選んで、エンターで確定だぞ。"),
         )?;
 
-        config.ignore_issues = ignore_issues
-            .into_iter()
-            .map(|issue| issue.unwrap())
-            .collect::<HashSet<_>>();
+        config.ignore_issues = Some(
+            ignore_issues
+                .into_iter()
+                .map(|issue| issue.unwrap())
+                .collect::<HashSet<_>>(),
+        );
     }
 
     let is_save_config = p.prompt(
@@ -200,10 +658,7 @@ fn wizard_(need_advanced: bool, only_reqired: bool) -> Result<Config> {
     )?;
 
     if is_save_config {
-        let mut file = File::create(Path::new(CONFIG_PATH))?;
-        let json = serde_json::to_string(&config)?;
-
-        file.write_all(json.as_bytes())?;
+        config.save(assets)?;
         p.success("おやっさん「よし、保存しといたから次回も同じ設定で行きたいなら`-u`をつけて実行してみてくれ。」")?;
     }
 
@@ -211,3 +666,28 @@ fn wizard_(need_advanced: bool, only_reqired: bool) -> Result<Config> {
 
     Ok(config)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn save_keeps_other_saved_settings() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("oyassan-config-{}.json", std::process::id()));
+        fs::write(&path, r##"{"fps":5,"background":"#123456"}"##)?;
+        let assets = Assets::locate(None, Some(path.clone()))?;
+
+        let answers = PartialConfig {
+            fps: Some(30),
+            ..Default::default()
+        };
+        answers.save(&assets)?;
+        let saved = PartialConfig::from_file(&assets)?;
+        fs::remove_file(&path)?;
+
+        assert_eq!(saved.fps, Some(30));
+        assert_eq!(saved.background.as_deref(), Some("#123456"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Result};
+use image::{imageops, DynamicImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+use super::sink::{output_path, FrameSink};
+use crate::Frame;
+
+#[derive(Debug, Clone)]
+pub struct DeltaOptions {
+    // この数のフレームごとに、丸ごとの画像を入れる。
+    pub keyframe_interval: u32,
+}
+
+// 連番画像の1枚ごとに、どこに貼る画像なのかを書いておく。
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaEntry {
+    pub file: String,
+    pub keyframe: bool,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DeltaManifest {
+    pub width: u32,
+    pub height: u32,
+    pub keyframe_interval: u32,
+    pub frames: Vec<DeltaEntry>,
+}
+
+// zip, tar, フォルダの中に入れる。入れられない出力では隣に置く。
+const DELTA_MANIFEST: &str = "delta.json";
+
+pub fn delta_manifest_path(out_path: &Path) -> PathBuf {
+    out_path.with_extension(DELTA_MANIFEST)
+}
+
+// 前のコマと違うところだけを切り出して渡し、キーフレームだけは丸ごと渡す。
+// 切り出した画像は前のコマの上に貼れば元に戻る。同じものを何度貼っても変わらない。
+pub struct DeltaSink {
+    inner: Box<dyn FrameSink>,
+    options: DeltaOptions,
+    prev: Option<RgbaImage>,
+    manifest: DeltaManifest,
+}
+
+impl DeltaSink {
+    pub fn new(inner: Box<dyn FrameSink>, options: &DeltaOptions) -> Self {
+        Self {
+            inner,
+            options: options.clone(),
+            prev: None,
+            manifest: DeltaManifest {
+                keyframe_interval: options.keyframe_interval,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn is_keyframe(&self, position: u32) -> bool {
+        position.is_multiple_of(self.options.keyframe_interval)
+    }
+
+    // startからduration分のコマとして、丸ごとか切り出した画像をinnerに渡す。
+    fn pass(
+        &mut self,
+        frame: &Frame,
+        rgba: &RgbaImage,
+        start: u32,
+        duration: u32,
+        region: Region,
+    ) -> Result<()> {
+        let img = match region {
+            Region::Full => frame.img.clone(),
+            Region::Crop(x, y, w, h) => {
+                DynamicImage::ImageRgba8(imageops::crop_imm(rgba, x, y, w, h).to_image())
+            }
+        };
+        let (x, y, width, height) = match region {
+            Region::Full => (0, 0, frame.img.width(), frame.img.height()),
+            Region::Crop(x, y, w, h) => (x, y, w, h),
+        };
+
+        let frame = Frame {
+            start,
+            duration,
+            img,
+            ..frame.clone()
+        };
+        for file in frame.file_names() {
+            self.manifest.frames.push(DeltaEntry {
+                file,
+                keyframe: region == Region::Full,
+                x,
+                y,
+                width,
+                height,
+            });
+        }
+
+        self.inner.add_frame(&frame)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Region {
+    Full,
+    Crop(u32, u32, u32, u32),
+}
+
+// 何も変わっていないときも、貼る画像は要るので左上の1pxにする。
+const UNCHANGED: Region = Region::Crop(0, 0, 1, 1);
+
+// 2枚の画像で違うところを囲む範囲
+fn changed_region(prev: &RgbaImage, img: &RgbaImage) -> Region {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for ((x, y, a), b) in prev.enumerate_pixels().zip(img.pixels()) {
+        if a != b {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+
+    if min_x > max_x {
+        return UNCHANGED;
+    }
+    Region::Crop(min_x, min_y, max_x - min_x + 1, max_y - min_y + 1)
+}
+
+impl FrameSink for DeltaSink {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()> {
+        if frame.duration == 0 {
+            return Ok(());
+        }
+
+        let img = frame.img.to_rgba8();
+        let mut changed = match &self.prev {
+            Some(prev) if prev.dimensions() == img.dimensions() => changed_region(prev, &img),
+            _ => Region::Full,
+        };
+        if self.prev.is_none() {
+            (self.manifest.width, self.manifest.height) = img.dimensions();
+        }
+
+        // キーフレームの位置で区切って、続いている間はまとめて渡す。
+        let end = frame.start + frame.duration;
+        let mut start = frame.start;
+        while start < end {
+            if self.is_keyframe(start) || changed == Region::Full {
+                self.pass(frame, &img, start, 1, Region::Full)?;
+                // 同じコマの残りは、いま渡したものと変わらない。
+                changed = UNCHANGED;
+                start += 1;
+                continue;
+            }
+
+            let run_end = (start + 1..end)
+                .find(|&p| self.is_keyframe(p))
+                .unwrap_or(end);
+            self.pass(frame, &img, start, run_end - start, changed)?;
+            start = run_end;
+        }
+
+        self.prev = Some(img);
+
+        Ok(())
+    }
+
+    fn add_extra_file(&mut self, name: &str, buf: &[u8]) -> Result<bool> {
+        self.inner.add_extra_file(name, buf)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<PathBuf> {
+        let json = serde_json::to_string_pretty(&self.manifest)?;
+        let added = self.inner.add_extra_file(DELTA_MANIFEST, json.as_bytes())?;
+
+        let path = self.inner.finish()?;
+        if !added {
+            fs::write(delta_manifest_path(&path), json)?;
+        }
+
+        Ok(path)
+    }
+}
+
+// 差分だけの連番画像が入ったzip, tar, フォルダ
+enum DeltaSource {
+    Dir(PathBuf),
+    Zip(ZipArchive<File>),
+    Tar(HashMap<String, Vec<u8>>),
+}
+
+impl DeltaSource {
+    fn open(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            return Ok(Self::Dir(path.to_path_buf()));
+        }
+
+        let file = File::open(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("zip") => Ok(Self::Zip(ZipArchive::new(file)?)),
+            Some("tar") => {
+                let mut files = HashMap::new();
+                for entry in tar::Archive::new(file).entries()? {
+                    let mut entry = entry?;
+                    let name = entry.path()?.display().to_string();
+                    let mut buf = Vec::new();
+                    entry.read_to_end(&mut buf)?;
+                    files.insert(name, buf);
+                }
+                Ok(Self::Tar(files))
+            }
+            _ => bail!("`{}` is not a zip, tar or folder", path.display()),
+        }
+    }
+
+    fn try_read(&mut self, name: &str) -> Result<Option<Vec<u8>>> {
+        match self {
+            Self::Dir(dir) => {
+                let path = dir.join(name);
+                Ok(path.is_file().then(|| fs::read(path)).transpose()?)
+            }
+            Self::Zip(zip) => {
+                let mut file = match zip.by_name(name) {
+                    Ok(file) => file,
+                    Err(ZipError::FileNotFound) => return Ok(None),
+                    Err(e) => return Err(e.into()),
+                };
+                let mut buf = Vec::new();
+                file.read_to_end(&mut buf)?;
+                Ok(Some(buf))
+            }
+            Self::Tar(files) => Ok(files.remove(name)),
+        }
+    }
+
+    fn read(&mut self, name: &str) -> Result<Vec<u8>> {
+        self.try_read(name)?
+            .ok_or_else(|| anyhow!("`{}` is missing from the delta frames", name))
+    }
+}
+
+// 差分の連番画像を、丸ごとの連番画像に戻してout_dirの下のフォルダに書き出す。
+pub fn reassemble(path: &Path, out_dir: &Path) -> Result<PathBuf> {
+    let mut source = DeltaSource::open(path)?;
+    // 中に無ければ、隣に置いたものを探す。
+    let manifest = match source.try_read(DELTA_MANIFEST)? {
+        Some(buf) => buf,
+        None => {
+            let manifest_path = delta_manifest_path(path);
+            fs::read(&manifest_path)
+                .map_err(|e| anyhow!("failed to read `{}`: {}", manifest_path.display(), e))?
+        }
+    };
+    let manifest: DeltaManifest = serde_json::from_slice(&manifest)?;
+
+    let out_path = output_path(out_dir, None);
+    fs::create_dir_all(&out_path)?;
+
+    let mut canvas: Option<RgbaImage> = None;
+    for entry in &manifest.frames {
+        let img = image::load_from_memory(&source.read(&entry.file)?)?.to_rgba8();
+        if img.dimensions() != (entry.width, entry.height) {
+            bail!("`{}` is not {}x{}", entry.file, entry.width, entry.height);
+        }
+
+        let canvas = match (&mut canvas, entry.keyframe) {
+            (canvas, true) => canvas.insert(img),
+            (Some(canvas), false) => {
+                imageops::replace(canvas, &img, entry.x as i64, entry.y as i64);
+                canvas
+            }
+            (None, false) => bail!("`{}` comes before any keyframe", entry.file),
+        };
+
+        canvas.save_with_format(out_path.join(&entry.file), image::ImageFormat::Png)?;
+    }
+
+    Ok(out_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+    use crate::io::{DirSink, TarSink};
+    use crate::{render, InputData, Pref, RenderOptions, Timing};
+
+    #[test]
+    fn reassembled_tar_matches_full_render() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("oyassan-delta-{}", std::process::id()));
+        let input = || InputData {
+            prefs: vec![Pref::Osaka, Pref::Kyoto, Pref::Osaka, Pref::Nara],
+            colors: vec![Rgb([255, 0, 0]), Rgb([0, 0, 255])],
+        };
+        let options = RenderOptions {
+            size: 200,
+            timing: Timing {
+                entry_secs: 0.3,
+                hold_last_secs: 0.5,
+                intro_secs: 0.2,
+                ..Default::default()
+            },
+            transition_frames: 1,
+            ..Default::default()
+        };
+
+        let mut full: Box<dyn FrameSink> = Box::new(DirSink::create(&dir.join("full"), false)?);
+        for frame in render(input(), &options)? {
+            full.add_frame(&frame?)?;
+        }
+        let full = full.finish()?;
+
+        let tar = Box::new(TarSink::create(&dir.join("delta"), false)?);
+        let mut delta: Box<dyn FrameSink> = Box::new(DeltaSink::new(
+            tar,
+            &DeltaOptions {
+                keyframe_interval: 4,
+            },
+        ));
+        for frame in render(input(), &options)? {
+            delta.add_frame(&frame?)?;
+        }
+        let tar = delta.finish()?;
+        assert!(!delta_manifest_path(&tar).exists());
+
+        let out = reassemble(&tar, &dir.join("out"))?;
+
+        let mut names = fs::read_dir(&full)?
+            .map(|entry| Ok(entry?.file_name()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        assert_eq!(names.len(), fs::read_dir(&out)?.count());
+        for name in names {
+            let expected = image::open(full.join(&name))?.to_rgba8();
+            let actual = image::open(out.join(&name))?.to_rgba8();
+            assert!(expected == actual, "{:?} differs", name);
+        }
+
+        fs::remove_dir_all(&dir)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::png::encode_png;
+use super::sink::{output_path, FrameSink};
+use crate::Frame;
+
+pub struct DirSink {
+    pub path: PathBuf,
+    optimize_png: bool,
+}
+
+impl DirSink {
+    pub fn create(out_dir: &Path, optimize_png: bool) -> Result<Self> {
+        let path = output_path(out_dir, None);
+        fs::create_dir_all(&path)?;
+
+        Ok(Self { path, optimize_png })
+    }
+}
+
+impl FrameSink for DirSink {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()> {
+        let mut names = frame.file_names();
+        let Some(first) = names.next().map(|name| self.path.join(name)) else {
+            return Ok(());
+        };
+
+        fs::write(&first, encode_png(&frame.img, self.optimize_png)?)?;
+        for name in names {
+            fs::copy(&first, self.path.join(name))?;
+        }
+
+        Ok(())
+    }
+
+    fn add_extra_file(&mut self, name: &str, buf: &[u8]) -> Result<bool> {
+        fs::write(self.path.join(name), buf)?;
+
+        Ok(true)
+    }
+
+    fn finish(self: Box<Self>) -> Result<PathBuf> {
+        Ok(self.path)
+    }
+}
@@ -0,0 +1,220 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::sink::{frame_dir, Recorder, Recording};
+use crate::{FrameInfo, Timing};
+
+// CMX3600の1つのEDLに入るイベントの数
+const MAX_EDL_EVENTS: usize = 999;
+
+#[derive(Debug, Clone)]
+pub struct EditListOptions {
+    pub timing: Timing,
+    // マーカーとコメントに使う。{number}, {index}, {pref}, {count}が使える。
+    pub caption: String,
+    pub edl: bool,
+    pub fcpxml: bool,
+}
+
+// 連番画像を静止画として並べたEDLとFCPXMLを、出力先の隣に置く。
+pub struct EditListWriter {
+    options: EditListOptions,
+}
+
+impl EditListWriter {
+    pub fn new(options: &EditListOptions) -> Self {
+        Self {
+            options: options.clone(),
+        }
+    }
+
+    fn fps(&self) -> u32 {
+        self.options.timing.fps()
+    }
+
+    // イントロと途中のコマにはコメントやマーカーをつけない。
+    fn caption(&self, entry: &FrameInfo) -> Option<String> {
+        (entry.pref.is_some() && !entry.transition).then(|| entry.fill(&self.options.caption))
+    }
+
+    // CMX3600のイベント番号は3桁までなので、収まらなければ999件ずつの別のEDLに分ける。
+    // 録画側のタイムコードは分けても通しのままにする。
+    fn edls(&self, title: &str, entries: &[FrameInfo]) -> Result<Vec<(String, String)>> {
+        let reels = entries.chunks(MAX_EDL_EVENTS).collect::<Vec<_>>();
+        if reels.len() <= 1 {
+            return Ok(vec![(title.to_string(), self.edl(title, entries)?)]);
+        }
+
+        // 名前順に並べたときに順番どおりになるよう、番号の桁をそろえる。
+        let width = reels.len().to_string().len();
+        reels
+            .iter()
+            .enumerate()
+            .map(|(n, entries)| {
+                let title = format!("{}-{:0width$}", title, n + 1, width = width);
+                let edl = self.edl(&title, entries)?;
+                Ok((title, edl))
+            })
+            .collect()
+    }
+
+    fn edl(&self, title: &str, entries: &[FrameInfo]) -> Result<String> {
+        let mut edl = String::new();
+        writeln!(edl, "TITLE: {}", title)?;
+        writeln!(edl, "FCM: NON-DROP FRAME")?;
+        writeln!(edl)?;
+
+        for (idx, entry) in entries.iter().enumerate() {
+            writeln!(
+                edl,
+                "{:03}  AX       V     C        {} {} {} {}",
+                idx + 1,
+                self.timecode(0),
+                self.timecode(entry.duration),
+                self.timecode(entry.start),
+                self.timecode(entry.end())
+            )?;
+            writeln!(edl, "* FROM CLIP NAME: {}", entry.file_name())?;
+            if let Some(caption) = self.caption(entry) {
+                writeln!(edl, "* COMMENT: {}", caption)?;
+            }
+            writeln!(edl)?;
+        }
+
+        Ok(edl)
+    }
+
+    fn timecode(&self, frames: u32) -> String {
+        let fps = self.fps();
+        let secs = frames / fps;
+
+        format!(
+            "{:02}:{:02}:{:02}:{:02}",
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60,
+            frames % fps
+        )
+    }
+
+    fn fcpxml(&self, title: &str, recording: &Recording, frame_dir: &Path) -> Result<String> {
+        let (width, height) = recording.size()?;
+        let fps = self.fps();
+        let length = recording.length();
+        let duration = |frames: u32| format!("{}/{}s", frames, fps);
+
+        let mut xml = String::new();
+        writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#)?;
+        writeln!(xml, "<!DOCTYPE fcpxml>")?;
+        writeln!(xml, r#"<fcpxml version="1.9">"#)?;
+        writeln!(xml, "  <resources>")?;
+        writeln!(
+            xml,
+            r#"    <format id="r1" frameDuration="{}" width="{}" height="{}"/>"#,
+            duration(1),
+            width,
+            height
+        )?;
+        writeln!(
+            xml,
+            r#"    <format id="r2" width="{}" height="{}"/>"#,
+            width, height
+        )?;
+        for entry in &recording.entries {
+            writeln!(
+                xml,
+                r#"    <asset id="a{}" name="{}" src="{}" start="0s" duration="0s" hasVideo="1" format="r2"/>"#,
+                entry.start,
+                entry.file_name(),
+                file_url(&frame_dir.join(entry.file_name()))
+            )?;
+        }
+        writeln!(xml, "  </resources>")?;
+        writeln!(xml, "  <library>")?;
+        writeln!(xml, r#"    <event name="oyassan">"#)?;
+        writeln!(xml, r#"      <project name="{}">"#, escape(title))?;
+        writeln!(
+            xml,
+            r#"        <sequence format="r1" duration="{}" tcStart="0s" tcFormat="NDF">"#,
+            duration(length)
+        )?;
+        writeln!(xml, "          <spine>")?;
+        for entry in &recording.entries {
+            writeln!(
+                xml,
+                r#"            <video ref="a{}" offset="{}" name="{}" start="0s" duration="{}">"#,
+                entry.start,
+                duration(entry.start),
+                entry.file_name(),
+                duration(entry.duration)
+            )?;
+            if let Some(caption) = self.caption(entry) {
+                writeln!(
+                    xml,
+                    r#"              <marker start="0s" duration="{}" value="{}"/>"#,
+                    duration(1),
+                    escape(&caption)
+                )?;
+            }
+            writeln!(xml, "            </video>")?;
+        }
+        writeln!(xml, "          </spine>")?;
+        writeln!(xml, "        </sequence>")?;
+        writeln!(xml, "      </project>")?;
+        writeln!(xml, "    </event>")?;
+        writeln!(xml, "  </library>")?;
+        writeln!(xml, "</fcpxml>")?;
+
+        Ok(xml)
+    }
+}
+
+impl Recorder for EditListWriter {
+    fn write(&self, recording: &Recording, out_path: &Path) -> Result<()> {
+        let title = out_path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        if self.options.edl {
+            for (name, edl) in self.edls(&title, &recording.entries)? {
+                fs::write(out_path.with_file_name(name).with_extension("edl"), edl)?;
+            }
+        }
+        if self.options.fcpxml {
+            let xml = self.fcpxml(&title, recording, &frame_dir(out_path)?)?;
+            fs::write(out_path.with_extension("fcpxml"), xml)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// 区切り文字以外の記号や日本語はパーセントエンコードする。
+fn file_url(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+
+    let mut url = String::from("file://");
+    if !path.starts_with('/') {
+        url.push('/');
+    }
+    for b in path.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' | b':' => {
+                url.push(b as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", b)),
+        }
+    }
+
+    url
+}
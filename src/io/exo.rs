@@ -0,0 +1,456 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, bail, Result};
+
+use super::sink::{frame_dir, Recorder, Recording};
+use crate::{FrameInfo, Timing};
+
+#[derive(Debug, Clone)]
+pub struct ExoOptions {
+    pub timing: Timing,
+    pub layer: u32,
+    pub x: f64,
+    pub y: f64,
+}
+
+// テンプレート中の{frame}を画像の場所で埋め、残りはFrameInfoに任せる。
+fn fill(value: &str, entry: &FrameInfo, frame_dir: &Path) -> String {
+    let frame = frame_dir.join(entry.file_name()).display().to_string();
+    entry.fill(&value.replace("{frame}", &frame))
+}
+
+#[derive(Debug, Clone)]
+struct Section {
+    name: String,
+    values: Vec<(String, String)>,
+}
+
+impl Section {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn set(&mut self, key: &str, value: String) {
+        match self.values.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value,
+            None => self.values.push((key.to_string(), value)),
+        }
+    }
+
+    fn frame_of(&self, key: &str) -> Result<u32> {
+        let raw = self
+            .get(key)
+            .ok_or_else(|| anyhow!("[{}] has no `{}`", self.name, key))?;
+
+        raw.parse()
+            .map_err(|e| anyhow!("[{}] {}={}: {}", self.name, key, raw, e))
+    }
+}
+
+// [N]と、それに続く[N.0], [N.1]...をひとまとめにしたもの
+#[derive(Debug, Clone)]
+struct Object {
+    head: Section,
+    parts: Vec<Section>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExoTemplate {
+    exedit: Section,
+    objects: Vec<Object>,
+    // テンプレートのフレームレート (rate / scale)。書いていなければ出力に合わせる。
+    fps: Option<f64>,
+}
+
+impl ExoTemplate {
+    pub fn load(path: &Path) -> Result<Self> {
+        let bytes =
+            fs::read(path).map_err(|e| anyhow!("failed to read `{}`: {}", path.display(), e))?;
+        let (text, _, had_errors) = encoding_rs::SHIFT_JIS.decode(&bytes);
+        if had_errors {
+            bail!("`{}` is not a Shift_JIS exo file", path.display());
+        }
+
+        let mut sections: Vec<Section> = Vec::new();
+        for line in text.lines() {
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                sections.push(Section {
+                    name: name.to_string(),
+                    values: Vec::new(),
+                });
+            } else if let Some((key, value)) = line.split_once('=') {
+                let Some(section) = sections.last_mut() else {
+                    bail!("`{}`: value outside of a section", path.display());
+                };
+                section.values.push((key.to_string(), value.to_string()));
+            }
+        }
+
+        let mut sections = sections.into_iter();
+        let Some(exedit) = sections.next().filter(|s| s.name == "exedit") else {
+            bail!("`{}` does not start with [exedit]", path.display());
+        };
+
+        let mut objects: Vec<Object> = Vec::new();
+        for section in sections {
+            match section.name.split_once('.') {
+                None => objects.push(Object {
+                    head: section,
+                    parts: Vec::new(),
+                }),
+                Some((id, _)) => match objects.last_mut() {
+                    Some(object) if object.head.name == id => object.parts.push(section),
+                    _ => bail!("[{}] does not follow its object", section.name),
+                },
+            }
+        }
+
+        if !objects
+            .iter()
+            .flat_map(|o| &o.parts)
+            .any(|p| p.values.iter().any(|(_, v)| v.contains("{frame}")))
+        {
+            bail!(
+                "`{}` has no object with a {{frame}} placeholder",
+                path.display()
+            );
+        }
+
+        for object in &objects {
+            object.head.frame_of("start")?;
+            object.head.frame_of("end")?;
+        }
+
+        let fps = match exedit.get("rate") {
+            Some(_) => {
+                let scale = match exedit.get("scale") {
+                    Some(_) => exedit.frame_of("scale")?,
+                    None => 1,
+                };
+                let rate = exedit.frame_of("rate")?;
+                if rate == 0 || scale == 0 {
+                    bail!("`{}`: rate and scale must be at least 1", path.display());
+                }
+                Some(rate as f64 / scale as f64)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            exedit,
+            objects,
+            fps,
+        })
+    }
+
+    // テンプレートを1コマごとにそのコマの位置へずらしながら複製する。
+    // 次のコマと重ならないよう、コマの長さを超える部分は切り詰める。
+    // フレームレートは出力に合わせ、テンプレート中の長さも同じ秒数になるよう直す。
+    fn render(&self, entries: &[FrameInfo], frame_dir: &Path, fps: u32) -> Result<String> {
+        let length = entries.last().map(|e| e.end()).unwrap_or_default();
+        let ratio = self.fps.map(|t| fps as f64 / t).unwrap_or(1.0);
+        let mut exedit = self.exedit.clone();
+        exedit.set("rate", fps.to_string());
+        exedit.set("scale", String::from("1"));
+        exedit.set("length", length.to_string());
+
+        let mut exo = String::new();
+        write_section(&mut exo, &exedit)?;
+
+        let mut id = 0;
+        for entry in entries {
+            for object in &self.objects {
+                let (start, end) = rescale(
+                    object.head.frame_of("start")?,
+                    object.head.frame_of("end")?,
+                    ratio,
+                );
+                if start > entry.duration {
+                    continue;
+                }
+                let end = end.min(entry.duration);
+
+                let mut head = object.head.clone();
+                head.name = id.to_string();
+                head.set("start", (entry.start + start).to_string());
+                head.set("end", (entry.start + end).to_string());
+                write_section(&mut exo, &head)?;
+
+                for (n, part) in object.parts.iter().enumerate() {
+                    let mut part = part.clone();
+                    part.name = format!("{}.{}", id, n);
+                    for (key, value) in part.values.iter_mut() {
+                        *value = if key == "text" {
+                            fill_text(value, entry, frame_dir)?
+                        } else {
+                            fill(value, entry, frame_dir)
+                        };
+                    }
+                    write_section(&mut exo, &part)?;
+                }
+
+                id += 1;
+            }
+        }
+
+        Ok(exo)
+    }
+}
+
+// テンプレートのstartとendを、ratio倍のフレームレートでのコマ番号に直す。
+// startは1始まりで、endはそのコマまでを含む。
+fn rescale(start: u32, end: u32, ratio: f64) -> (u32, u32) {
+    let start = (start.saturating_sub(1) as f64 * ratio).round() as u32 + 1;
+    let end = ((end as f64 * ratio).round() as u32).max(start);
+
+    (start, end)
+}
+
+fn write_section(exo: &mut String, section: &Section) -> Result<()> {
+    writeln!(exo, "[{}]", section.name)?;
+    for (key, value) in &section.values {
+        writeln!(exo, "{}={}", key, value)?;
+    }
+
+    Ok(())
+}
+
+// テキストオブジェクトの本文は、UTF-16LEを16進数にして0で埋めた固定長になっている。
+fn fill_text(hex: &str, entry: &FrameInfo, frame_dir: &Path) -> Result<String> {
+    let units = hex
+        .as_bytes()
+        .chunks(4)
+        .map(|c| {
+            let c = std::str::from_utf8(c)?;
+            if c.len() != 4 {
+                bail!("odd length");
+            }
+            let [lo, hi] = [&c[0..2], &c[2..4]].map(|b| u16::from_str_radix(b, 16));
+            Ok(lo? | (hi? << 8))
+        })
+        .collect::<Result<Vec<u16>>>()
+        .map_err(|e| anyhow!("broken text in exo template: {}", e))?;
+
+    let end = units.iter().position(|&u| u == 0).unwrap_or(units.len());
+    let text = String::from_utf16(&units[..end])?;
+    let filled = fill(&text, entry, frame_dir)
+        .encode_utf16()
+        .collect::<Vec<_>>();
+    if filled.len() >= units.len() {
+        bail!("text `{}` is too long for the exo template", text);
+    }
+
+    let mut out = String::with_capacity(hex.len());
+    for u in filled.iter().chain(std::iter::repeat(&0)).take(units.len()) {
+        let [lo, hi] = u.to_le_bytes();
+        write!(out, "{:02x}{:02x}", lo, hi)?;
+    }
+
+    Ok(out)
+}
+
+// 連番画像を並べたAviUtlのexoを、出力先の隣に置く。
+// templateを渡したら、optionsの配置の代わりにそれを1コマごとに複製して埋める。
+pub struct ExoWriter {
+    options: ExoOptions,
+    template: Option<ExoTemplate>,
+}
+
+impl ExoWriter {
+    pub fn new(options: &ExoOptions, template: Option<ExoTemplate>) -> Self {
+        Self {
+            options: options.clone(),
+            template,
+        }
+    }
+
+    fn timeline(&self, recording: &Recording, frame_dir: &Path) -> Result<String> {
+        let (width, height) = recording.size()?;
+
+        let mut exo = String::new();
+        writeln!(exo, "[exedit]")?;
+        writeln!(exo, "width={}", width)?;
+        writeln!(exo, "height={}", height)?;
+        writeln!(exo, "rate={}", self.options.timing.fps())?;
+        writeln!(exo, "scale=1")?;
+        writeln!(exo, "length={}", recording.length())?;
+        writeln!(exo, "audio_rate=44100")?;
+        writeln!(exo, "audio_ch=2")?;
+
+        for (idx, entry) in recording.entries.iter().enumerate() {
+            // AviUtlのフレーム番号は1始まり
+            writeln!(exo, "[{}]", idx)?;
+            writeln!(exo, "start={}", entry.start + 1)?;
+            writeln!(exo, "end={}", entry.end())?;
+            writeln!(exo, "layer={}", self.options.layer.max(1))?;
+            writeln!(exo, "overlay=1")?;
+            writeln!(exo, "camera=0")?;
+            writeln!(exo, "[{}.0]", idx)?;
+            writeln!(exo, "_name=画像ファイル")?;
+            writeln!(exo, "file={}", frame_dir.join(entry.file_name()).display())?;
+            writeln!(exo, "[{}.1]", idx)?;
+            writeln!(exo, "_name=標準描画")?;
+            writeln!(exo, "X={:.1}", self.options.x)?;
+            writeln!(exo, "Y={:.1}", self.options.y)?;
+            writeln!(exo, "Z=0.0")?;
+            writeln!(exo, "拡大率=100.00")?;
+            writeln!(exo, "透明度=0.0")?;
+            writeln!(exo, "回転=0.00")?;
+            writeln!(exo, "blend=0")?;
+        }
+
+        Ok(exo)
+    }
+}
+
+impl Recorder for ExoWriter {
+    fn write(&self, recording: &Recording, out_path: &Path) -> Result<()> {
+        let frame_dir = frame_dir(out_path)?;
+        let exo = match &self.template {
+            Some(template) => {
+                template.render(&recording.entries, &frame_dir, self.options.timing.fps())?
+            }
+            None => self.timeline(recording, &frame_dir)?,
+        };
+
+        write_sjis(&out_path.with_extension("exo"), &exo)
+    }
+}
+
+// AviUtlはShift_JISでCRLFのexoしか読めない。
+pub fn write_sjis(path: &Path, text: &str) -> Result<()> {
+    let text = text.replace("\r\n", "\n").replace('\n', "\r\n");
+    let (bytes, _, had_errors) = encoding_rs::SHIFT_JIS.encode(&text);
+    if had_errors {
+        bail!(
+            "`{}` contains characters that Shift_JIS cannot represent",
+            path.display()
+        );
+    }
+    fs::write(path, bytes)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pref;
+
+    // AviUtlのテキストオブジェクトの本文の長さ
+    const TEXT_UNITS: usize = 4096;
+
+    const TEMPLATE: &str = "[exedit]
+width=1280
+height=720
+rate=30
+scale=1
+length=30
+[0]
+start=1
+end=30
+layer=1
+[0.0]
+_name=画像ファイル
+file={frame}
+";
+
+    fn load(name: &str, body: &str) -> Result<ExoTemplate> {
+        let path =
+            std::env::temp_dir().join(format!("oyassan-{}-{}.exo", name, std::process::id()));
+        write_sjis(&path, body)?;
+        let template = ExoTemplate::load(&path);
+        fs::remove_file(&path)?;
+
+        template
+    }
+
+    fn entry() -> FrameInfo {
+        FrameInfo {
+            index: 2,
+            start: 40,
+            duration: 20,
+            pref: Some(Pref::Osaka),
+            visit_count: 3,
+            tint_color: None,
+            transition: false,
+        }
+    }
+
+    fn encode_text(text: &str) -> String {
+        text.encode_utf16()
+            .chain(std::iter::repeat(0))
+            .take(TEXT_UNITS)
+            .flat_map(u16::to_le_bytes)
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    fn decode_text(hex: &str) -> String {
+        let units = hex
+            .as_bytes()
+            .chunks(4)
+            .map(|c| {
+                let c = std::str::from_utf8(c).unwrap();
+                u16::from_str_radix(&c[0..2], 16).unwrap()
+                    | (u16::from_str_radix(&c[2..4], 16).unwrap() << 8)
+            })
+            .take_while(|&u| u != 0)
+            .collect::<Vec<_>>();
+
+        String::from_utf16(&units).unwrap()
+    }
+
+    #[test]
+    fn load_requires_frame_placeholder() {
+        assert!(load("with-frame", TEMPLATE).is_ok());
+
+        let err = load("without-frame", &TEMPLATE.replace("{frame}", "a.png")).unwrap_err();
+        assert!(err.to_string().contains("{frame}"), "{}", err);
+    }
+
+    #[test]
+    fn load_rejects_part_without_object() {
+        let body = TEMPLATE.replace("[0.0]", "[1.0]");
+
+        assert!(load("orphan-part", &body).is_err());
+    }
+
+    #[test]
+    fn fill_text_round_trips_utf16le() {
+        let hex = encode_text("{number}件目: {pref} ({count}回目)");
+        let filled = fill_text(&hex, &entry(), Path::new("frames")).unwrap();
+
+        assert_eq!(filled.len(), hex.len());
+        assert_eq!(decode_text(&filled), "3件目: 大阪府 (3回目)");
+    }
+
+    #[test]
+    fn fill_text_rejects_overflow() {
+        // 埋めると1か所20文字ほどになり、固定長に収まらない。
+        let hex = encode_text(&"{frame}".repeat(500));
+        let err = fill_text(&hex, &entry(), Path::new("frames")).unwrap_err();
+
+        assert!(err.to_string().contains("too long"), "{}", err);
+    }
+
+    #[test]
+    fn render_uses_output_frame_rate() {
+        let template = load("rate", TEMPLATE).unwrap();
+        let exo = template
+            .render(&[entry()], Path::new("frames"), 10)
+            .unwrap();
+        let lines = exo.lines().collect::<Vec<_>>();
+
+        assert!(lines.contains(&"rate=10"));
+        assert!(lines.contains(&"scale=1"));
+        // 30fpsで1秒のオブジェクトは、10fpsでは10コマになる。
+        assert!(lines.contains(&"start=41"));
+        assert!(lines.contains(&"end=50"));
+    }
+}
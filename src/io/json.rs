@@ -1,8 +1,10 @@
+use std::fmt::Display;
+
 use anyhow::Result;
 use image::{Pixel, Rgb};
 use serde::Deserialize;
 
-use crate::{Pref, PrefDict};
+use crate::{Assets, Pref, PrefDict};
 
 use super::{config::Config, Issue};
 
@@ -12,6 +14,20 @@ pub struct RawJson {
     colors: Vec<String>,
 }
 
+#[derive(Debug, Clone)]
+pub struct IssueError {
+    pub issue: Issue,
+    pub msg: String,
+}
+
+impl Display for IssueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl std::error::Error for IssueError {}
+
 #[derive(Debug, Clone, Hash)]
 pub struct InputData {
     pub prefs: Vec<Pref>,
@@ -19,55 +35,46 @@ pub struct InputData {
 }
 
 impl InputData {
-    pub fn from_raw_json(raw_json: RawJson, pref_dict: &PrefDict, config: &Config) -> Self {
-        let prefs = sanitize_raw_prefs(raw_json.prefs, pref_dict);
-
-        let say_err = |msg: String, issue: Issue| {
+    pub fn from_raw_json(raw_json: RawJson, pref_dict: &PrefDict, config: &Config) -> Result<Self> {
+        let say_err = |msg: String, issue: Issue| -> Result<()> {
             if config.ignore_issues.contains(&issue) {
-                println!("{}", msg);
+                eprintln!("{}", msg);
+                Ok(())
             } else {
-                panic!("{}", msg);
+                Err(IssueError { issue, msg }.into())
             }
         };
 
-        let prefs = prefs
-            .into_iter()
-            .filter_map(|maybe_pref| match maybe_pref {
-                Ok(pref) => Some(pref),
-                Err(raw) => {
-                    say_err(
-                        format!(
-                            "おやっさん「おい！`{}`なんて都道府県、地図にないぞ！」",
-                            raw
-                        ),
-                        Issue::InvalidPref,
-                    );
-
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
-
-        let mut colors = sanitize_raw_colors(raw_json.colors)
-            .into_iter()
-            .filter_map(|c| match c {
-                Ok(c) => Some(c),
-                Err(raw) => {
-                    say_err(
-                        format!("おやっさん「おい！`{}`ってどんな色かわかんねぇよ！」", raw),
-                        Issue::InvalidColor,
-                    );
-
-                    None
-                }
-            })
-            .collect::<Vec<_>>();
+        let mut prefs = Vec::new();
+        for maybe_pref in sanitize_raw_prefs(raw_json.prefs, pref_dict) {
+            match maybe_pref {
+                Ok(pref) => prefs.push(pref),
+                Err(raw) => say_err(
+                    format!(
+                        "おやっさん「おい！`{}`なんて都道府県、地図にないぞ！」",
+                        raw
+                    ),
+                    Issue::InvalidPref,
+                )?,
+            }
+        }
+
+        let mut colors = Vec::new();
+        for c in sanitize_raw_colors(raw_json.colors) {
+            match c {
+                Ok(c) => colors.push(c),
+                Err(raw) => say_err(
+                    format!("おやっさん「おい！`{}`ってどんな色かわかんねぇよ！」", raw),
+                    Issue::InvalidColor,
+                )?,
+            }
+        }
 
         if colors.is_empty() {
             say_err(
                 String::from("おやっさん「おい！色は少なくとも１種類指定してくれないと困るぞ。」"),
                 Issue::EmptyColor,
-            );
+            )?;
 
             colors = vec![
                 *Rgb::<u8>::from_slice(&[255, 0, 0]),
@@ -76,7 +83,7 @@ impl InputData {
             ];
         }
 
-        InputData { prefs, colors }
+        Ok(InputData { prefs, colors })
     }
 }
 
@@ -94,33 +101,34 @@ fn sanitize_raw_prefs(raw_prefs: Vec<String>, pref_dict: &PrefDict) -> Vec<Resul
 }
 
 fn sanitize_raw_colors(raw_colors: Vec<String>) -> Vec<Result<Rgb<u8>, String>> {
-    let to_rgb = |c: String| -> Result<Rgb<u8>, String> {
-        let color = if c.starts_with('#') {
-            if c.len() == 7 {
-                [&c[1..3], &c[3..5], &c[5..7]].map(|e| u8::from_str_radix(e, 16))
-            } else if c.len() == 4 {
-                [&c[1..3], &c[3..5], &c[5..7]].map(|e| u8::from_str_radix(e, 16))
-            } else {
-                return Err(c);
-            }
-        } else {
-            return Err(c);
-        };
+    raw_colors.into_iter().map(parse_color).collect::<Vec<_>>()
+}
 
-        if let [Ok(r), Ok(g), Ok(b)] = color {
-            Ok(*Rgb::from_slice(&[r, g, b]))
+pub fn parse_color(c: String) -> Result<Rgb<u8>, String> {
+    let color = if c.starts_with('#') && c.is_ascii() {
+        if c.len() == 7 {
+            [&c[1..3], &c[3..5], &c[5..7]].map(|e| u8::from_str_radix(e, 16))
+        } else if c.len() == 4 {
+            // #f0a -> #ff00aa
+            [&c[1..2], &c[2..3], &c[3..4]].map(|e| u8::from_str_radix(e, 16).map(|v| v * 17))
         } else {
-            Err(c)
+            return Err(c);
         }
+    } else {
+        return Err(c);
     };
 
-    raw_colors.into_iter().map(to_rgb).collect::<Vec<_>>()
+    if let [Ok(r), Ok(g), Ok(b)] = color {
+        Ok(*Rgb::from_slice(&[r, g, b]))
+    } else {
+        Err(c)
+    }
 }
 
-pub fn input(config: &Config) -> Result<InputData> {
-    let pref_dict = PrefDict::load_from_csv()?;
+pub fn input(config: &Config, assets: &Assets) -> Result<InputData> {
+    let pref_dict = PrefDict::load_from_csv(assets)?;
     let json_path = config.input_path.clone();
 
     let raw_json: RawJson = serde_json::from_str(std::fs::read_to_string(json_path)?.as_str())?;
-    Ok(InputData::from_raw_json(raw_json, &pref_dict, config))
+    InputData::from_raw_json(raw_json, &pref_dict, config)
 }
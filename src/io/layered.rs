@@ -0,0 +1,261 @@
+use std::collections::HashSet;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::path::PathBuf;
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+
+use super::config::Config;
+
+const ENV_PREFIX: &str = "OYASSAN_";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Default,
+    File,
+    Env,
+    Wizard,
+    Cli,
+}
+
+impl Display for Layer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Self::Default => "default",
+            Self::File => "file",
+            Self::Env => "env",
+            Self::Wizard => "wizard",
+            Self::Cli => "cli",
+        };
+
+        write!(f, "{}", s)
+    }
+}
+
+// 環境変数から読めて、--show-configで表示できる設定値
+pub trait ConfigValue: Sized {
+    fn parse_env(raw: &str) -> Result<Self>;
+
+    fn describe(&self) -> String;
+}
+
+impl ConfigValue for PathBuf {
+    fn parse_env(raw: &str) -> Result<Self> {
+        Ok(PathBuf::from(raw))
+    }
+
+    fn describe(&self) -> String {
+        self.display().to_string()
+    }
+}
+
+impl ConfigValue for String {
+    fn parse_env(raw: &str) -> Result<Self> {
+        Ok(raw.to_string())
+    }
+
+    fn describe(&self) -> String {
+        self.clone()
+    }
+}
+
+impl ConfigValue for bool {
+    fn parse_env(raw: &str) -> Result<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(true),
+            "0" | "false" | "no" | "off" => Ok(false),
+            _ => Err(anyhow!("`{}` is not a bool", raw)),
+        }
+    }
+
+    fn describe(&self) -> String {
+        self.to_string()
+    }
+}
+
+macro_rules! from_str_config_value {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl ConfigValue for $ty {
+                fn parse_env(raw: &str) -> Result<Self> {
+                    raw.trim()
+                        .parse()
+                        .map_err(|e| anyhow!("`{}`: {}", raw, e))
+                }
+
+                fn describe(&self) -> String {
+                    self.to_string()
+                }
+            }
+        )*
+    };
+}
+
+from_str_config_value!(u8, u16, u32, usize, f64);
+
+macro_rules! value_enum_config_value {
+    ($($ty:ty),* $(,)?) => {
+        $(
+            impl $crate::io::layered::ConfigValue for $ty {
+                fn parse_env(raw: &str) -> anyhow::Result<Self> {
+                    <Self as clap::ValueEnum>::from_str(raw.trim(), true)
+                        .map_err(|e| anyhow::anyhow!(e))
+                }
+
+                fn describe(&self) -> String {
+                    clap::ValueEnum::to_possible_value(self)
+                        .map(|value| value.get_name().to_string())
+                        .unwrap_or_default()
+                }
+            }
+        )*
+    };
+}
+
+pub(crate) use value_enum_config_value;
+
+impl<T: ConfigValue> ConfigValue for Option<T> {
+    fn parse_env(raw: &str) -> Result<Self> {
+        T::parse_env(raw).map(Some)
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Some(value) => value.describe(),
+            None => String::from("-"),
+        }
+    }
+}
+
+// カンマ区切りで複数指定できるもの
+impl<T: ConfigValue + ValueEnum + Hash + Eq> ConfigValue for HashSet<T> {
+    fn parse_env(raw: &str) -> Result<Self> {
+        raw.split(',')
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+            .map(T::parse_env)
+            .collect()
+    }
+
+    fn describe(&self) -> String {
+        let mut values = self.iter().map(T::describe).collect::<Vec<_>>();
+        values.sort();
+
+        format!("[{}]", values.join(", "))
+    }
+}
+
+pub fn env_value<T: ConfigValue>(field: &str) -> Result<Option<T>> {
+    let name = format!("{}{}", ENV_PREFIX, field.to_ascii_uppercase());
+    let Some(raw) = std::env::var(&name).ok().filter(|v| !v.trim().is_empty()) else {
+        return Ok(None);
+    };
+
+    T::parse_env(&raw)
+        .map(Some)
+        .map_err(|e| anyhow!("{}: {}", name, e))
+}
+
+// フィールドの一覧から、Config・PartialConfig・ConfigSourcesと重ね合わせの処理をまとめて作る。
+macro_rules! layered_config {
+    ($($field:ident: $ty:ty = $default:expr),* $(,)?) => {
+        #[derive(Clone, Debug, Serialize, Deserialize)]
+        #[serde(default)]
+        pub struct Config {
+            $(pub $field: $ty,)*
+        }
+
+        impl Default for Config {
+            fn default() -> Self {
+                Self {
+                    $($field: $default,)*
+                }
+            }
+        }
+
+        #[derive(Clone, Debug, Default, Serialize, Deserialize)]
+        #[serde(default)]
+        pub struct PartialConfig {
+            $(
+                #[serde(skip_serializing_if = "Option::is_none")]
+                pub $field: Option<$ty>,
+            )*
+        }
+
+        impl PartialConfig {
+            pub fn from_env() -> Result<Self> {
+                Ok(Self {
+                    $($field: $crate::io::layered::env_value(stringify!($field))?,)*
+                })
+            }
+
+            // otherにある値だけ上書きする。
+            pub fn merge(self, other: Self) -> Self {
+                Self {
+                    $($field: other.$field.or(self.$field),)*
+                }
+            }
+        }
+
+        #[derive(Debug, Clone)]
+        pub struct ConfigSources {
+            $(pub $field: $crate::io::layered::Layer,)*
+        }
+
+        impl Default for ConfigSources {
+            fn default() -> Self {
+                Self {
+                    $($field: $crate::io::layered::Layer::Default,)*
+                }
+            }
+        }
+
+        impl $crate::io::layered::LayeredConfig {
+            // 後から適用したレイヤーほど優先される。
+            pub fn apply(
+                &mut self,
+                layer: $crate::io::layered::Layer,
+                partial: PartialConfig,
+            ) -> &mut Self {
+                $(
+                    if let Some(value) = partial.$field {
+                        self.config.$field = value;
+                        self.sources.$field = layer;
+                    }
+                )*
+
+                self
+            }
+
+            pub(crate) fn rows(&self) -> Vec<(&'static str, String, $crate::io::layered::Layer)> {
+                use $crate::io::layered::ConfigValue;
+
+                vec![
+                    $((stringify!($field), self.config.$field.describe(), self.sources.$field),)*
+                ]
+            }
+        }
+    };
+}
+
+pub(crate) use layered_config;
+
+#[derive(Debug, Clone, Default)]
+pub struct LayeredConfig {
+    pub config: Config,
+    pub sources: super::config::ConfigSources,
+}
+
+impl Display for LayeredConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rows = self.rows();
+        let width = rows.iter().map(|(key, _, _)| key.len()).max().unwrap_or(0);
+
+        for (key, value, layer) in rows {
+            writeln!(f, "{:<width$} = {} ({})", key, value, layer, width = width)?;
+        }
+
+        Ok(())
+    }
+}
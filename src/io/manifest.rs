@@ -0,0 +1,124 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use super::sink::{Recorder, Recording};
+use crate::{Config, FrameInfo};
+
+// 1コマ分。イントロではpref, kanji, tint_colorが空になる。
+#[derive(Debug, Clone, Serialize)]
+struct ManifestFrame {
+    index: usize,
+    file: String,
+    start: u32,
+    duration: u32,
+    transition: bool,
+    pref: Option<String>,
+    kanji: Option<String>,
+    visit_count: usize,
+    tint_color: Option<String>,
+}
+
+impl ManifestFrame {
+    fn new(entry: &FrameInfo) -> Self {
+        Self {
+            index: entry.index,
+            file: entry.file_name(),
+            start: entry.start,
+            duration: entry.duration,
+            transition: entry.transition,
+            pref: entry.pref.as_ref().map(|pref| pref.as_key()),
+            kanji: entry.pref.is_some().then(|| entry.pref_name()),
+            visit_count: entry.visit_count,
+            tint_color: entry
+                .tint_color
+                .map(|c| format!("#{:02x}{:02x}{:02x}", c.0[0], c.0[1], c.0[2])),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Manifest<'a> {
+    input_sha256: &'a str,
+    config: &'a Config,
+    frames: Vec<ManifestFrame>,
+}
+
+// 入力ファイルのSHA-256。出力先を作る前に求めておく。
+pub fn input_sha256(path: &Path) -> Result<String> {
+    let input =
+        fs::read(path).map_err(|e| anyhow!("failed to read `{}`: {}", path.display(), e))?;
+
+    Ok(format!("{:x}", Sha256::digest(input)))
+}
+
+// どのコマがどの都道府県の何回目かを、manifest.jsonとmanifest.csvに書いておく。
+// zip, tar, フォルダには中に入れ、それ以外の出力では隣に置く。
+pub struct ManifestWriter {
+    config: Config,
+    input_sha256: String,
+}
+
+impl ManifestWriter {
+    pub fn new(config: &Config, input_sha256: String) -> Self {
+        Self {
+            config: config.clone(),
+            input_sha256,
+        }
+    }
+
+    fn json(&self, entries: &[FrameInfo]) -> Result<String> {
+        let manifest = Manifest {
+            input_sha256: &self.input_sha256,
+            config: &self.config,
+            frames: entries.iter().map(ManifestFrame::new).collect(),
+        };
+
+        Ok(serde_json::to_string_pretty(&manifest)?)
+    }
+
+    fn csv(&self, entries: &[FrameInfo]) -> Result<String> {
+        let mut csv = String::new();
+        writeln!(
+            csv,
+            "index,file,start,duration,transition,pref,kanji,visit_count,tint_color"
+        )?;
+
+        for frame in entries.iter().map(ManifestFrame::new) {
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{},{}",
+                frame.index,
+                frame.file,
+                frame.start,
+                frame.duration,
+                frame.transition,
+                frame.pref.unwrap_or_default(),
+                frame.kanji.unwrap_or_default(),
+                frame.visit_count,
+                frame.tint_color.unwrap_or_default()
+            )?;
+        }
+
+        Ok(csv)
+    }
+}
+
+impl Recorder for ManifestWriter {
+    fn extra_files(&self, recording: &Recording) -> Result<Vec<(String, Vec<u8>)>> {
+        Ok(vec![
+            (
+                String::from("manifest.json"),
+                self.json(&recording.entries)?.into_bytes(),
+            ),
+            (
+                String::from("manifest.csv"),
+                self.csv(&recording.entries)?.into_bytes(),
+            ),
+        ])
+    }
+}
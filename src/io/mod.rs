@@ -1,7 +1,35 @@
+mod anim;
+mod asset;
 mod config;
+mod delta;
+mod dir;
+mod edl;
+mod exo;
 mod json;
+mod layered;
+mod manifest;
+mod png;
+mod sink;
+mod subtitle;
+mod tar;
+mod y4m;
 mod zip;
 
+pub use anim::{AnimationOptions, ApngSink, GifSink, WebpSink};
+pub use asset::{AssetNotFound, Assets};
 pub use config::*;
-pub use json::{input, InputData};
-pub use zip::ZipBuilder;
+pub use delta::{
+    delta_manifest_path, reassemble, DeltaEntry, DeltaManifest, DeltaOptions, DeltaSink,
+};
+pub use dir::DirSink;
+pub use edl::{EditListOptions, EditListWriter};
+pub use exo::{ExoOptions, ExoTemplate, ExoWriter};
+pub use json::{input, parse_color, InputData, IssueError};
+pub use layered::{ConfigValue, Layer, LayeredConfig};
+pub use manifest::{input_sha256, ManifestWriter};
+pub use png::encode_png;
+pub use sink::{FrameSink, Recorder, Recording, RecordingSink};
+pub use subtitle::{SubtitleOptions, SubtitleWriter};
+pub use tar::TarSink;
+pub use y4m::Y4mSink;
+pub use zip::{ZipBuilder, ZipOptions};
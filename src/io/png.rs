@@ -0,0 +1,126 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::iter;
+
+use anyhow::Result;
+use color_quant::NeuQuant;
+use image::{DynamicImage, RgbaImage};
+
+// 減色するときの学習の粗さ。1が一番丁寧で遅い。
+const QUANTIZE_SAMPLE: i32 = 10;
+// 減色するときも、よく使われているこの数の色はそのまま残す。
+const KEPT_COLORS: usize = 128;
+
+pub fn encode_png(img: &DynamicImage, optimize: bool) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    if optimize {
+        write_indexed(&img.to_rgba8(), &mut buf)?;
+    } else {
+        img.write_to(&mut Cursor::new(&mut buf), image::ImageFormat::Png)?;
+    }
+
+    Ok(buf)
+}
+
+// 地図はほとんど同じ色なので、256色のパレットにして書く。
+fn write_indexed(img: &RgbaImage, buf: &mut Vec<u8>) -> Result<()> {
+    let palette = palette_of(img);
+
+    // パレットにない色は一番近い色にする。同じ色は何度も出てくるので、引いた結果を覚えておく。
+    let mut index_of = palette
+        .iter()
+        .enumerate()
+        .map(|(idx, &c)| (c, idx as u8))
+        .collect::<HashMap<_, _>>();
+    let indices = img
+        .pixels()
+        .map(|p| {
+            *index_of
+                .entry(p.0)
+                .or_insert_with(|| nearest(&palette, p.0))
+        })
+        .collect::<Vec<_>>();
+
+    let mut encoder = png::Encoder::new(buf, img.width(), img.height());
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_compression(png::Compression::High);
+    encoder.set_palette(
+        palette
+            .iter()
+            .flat_map(|c| [c[0], c[1], c[2]])
+            .collect::<Vec<_>>(),
+    );
+    if palette.iter().any(|c| c[3] < 255) {
+        encoder.set_trns(palette.iter().map(|c| c[3]).collect::<Vec<_>>());
+    }
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&indices)?;
+    writer.finish()?;
+
+    Ok(())
+}
+
+// 256色に収まればそのまま使う。収まらなければ、塗りや背景のようによく使われている色は残して、
+// ふちのなめらかな部分だけを減色する。
+fn palette_of(img: &RgbaImage) -> Vec<[u8; 4]> {
+    let mut counts = HashMap::<[u8; 4], usize>::new();
+    for p in img.pixels() {
+        *counts.entry(p.0).or_default() += 1;
+    }
+
+    // 同じ画像からは同じファイルができるよう、並びを決めておく。
+    let mut colors = counts.into_iter().collect::<Vec<_>>();
+    colors.sort_by_key(|&(c, n)| (Reverse(n), c));
+    if colors.len() <= 256 {
+        return colors.into_iter().map(|(c, _)| c).collect();
+    }
+
+    let (kept, rest) = colors.split_at(KEPT_COLORS);
+    let rest_pixels = rest
+        .iter()
+        .flat_map(|&(c, n)| iter::repeat_n(c, n))
+        .flatten()
+        .collect::<Vec<_>>();
+    let quant = NeuQuant::new(QUANTIZE_SAMPLE, 256 - KEPT_COLORS, &rest_pixels);
+
+    kept.iter()
+        .map(|&(c, _)| c)
+        .chain(
+            quant
+                .color_map_rgba()
+                .chunks(4)
+                .map(|c| [c[0], c[1], c[2], c[3]]),
+        )
+        .collect()
+}
+
+// 透明に近い色は、色よりも透明度の違いのほうが目立つので、透明度をかけた値で比べる。
+fn nearest(palette: &[[u8; 4]], color: [u8; 4]) -> u8 {
+    let premultiply = |c: [u8; 4]| {
+        let a = c[3] as i32;
+        [
+            c[0] as i32 * a / 255,
+            c[1] as i32 * a / 255,
+            c[2] as i32 * a / 255,
+            a,
+        ]
+    };
+    let target = premultiply(color);
+    let distance = |c: [u8; 4]| {
+        premultiply(c)
+            .iter()
+            .zip(target)
+            .map(|(&a, b)| (a - b).pow(2))
+            .sum::<i32>()
+    };
+
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, &c)| distance(c))
+        .map(|(idx, _)| idx as u8)
+        .unwrap_or_default()
+}
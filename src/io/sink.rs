@@ -0,0 +1,144 @@
+use std::fs::{self, File};
+use std::io::ErrorKind;
+use std::path::{self, Path, PathBuf};
+
+use anyhow::{bail, Result};
+use nanoid::nanoid;
+
+use crate::{Frame, FrameInfo};
+
+pub trait FrameSink: Send {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()>;
+
+    // 連番画像と一緒に入れておくファイル。入れる場所がない出力ではfalseを返すので、
+    // 呼んだ側で出力の隣に置く。
+    fn add_extra_file(&mut self, _name: &str, _buf: &[u8]) -> Result<bool> {
+        Ok(false)
+    }
+
+    // 書き出しを終えて、出力先のパスを返す。
+    fn finish(self: Box<Self>) -> Result<PathBuf>;
+}
+
+pub fn output_path(out_dir: &Path, ext: Option<&str>) -> PathBuf {
+    let nanoid = nanoid!(4);
+    let timestamp = chrono::Local::now().format("%m%d-%H%M").to_string();
+    let name = format!("{}-{}", timestamp, nanoid);
+
+    match ext {
+        Some(ext) => out_dir.join(format!("{}.{}", name, ext)),
+        None => out_dir.join(name),
+    }
+}
+
+pub fn create_output_file(out_dir: &Path, path: &Path) -> Result<File> {
+    let file = match File::create_new(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            fs::create_dir_all(out_dir)?;
+            File::create_new(path)?
+        }
+        Err(e) => Err(e)?,
+    };
+
+    Ok(file)
+}
+
+// zipやtarは同じ名前のフォルダに展開してもらう前提で、連番画像の場所を決める。
+pub fn frame_dir(out_path: &Path) -> Result<PathBuf> {
+    let dir = if out_path.is_dir() {
+        out_path.to_path_buf()
+    } else {
+        out_path.with_extension("")
+    };
+
+    Ok(path::absolute(dir)?)
+}
+
+// 書き出したコマの大きさと並び
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub size: Option<(u32, u32)>,
+    pub entries: Vec<FrameInfo>,
+}
+
+impl Recording {
+    pub fn size(&self) -> Result<(u32, u32)> {
+        match self.size {
+            Some(size) => Ok(size),
+            None => bail!("no frames to write"),
+        }
+    }
+
+    // タイムライン全体の長さ (fps基準のフレーム数)
+    pub fn length(&self) -> u32 {
+        self.entries.last().map(|e| e.end()).unwrap_or_default()
+    }
+}
+
+// 書き出したコマの並びから、字幕やexoのような別のファイルを作るもの
+pub trait Recorder: Send {
+    // 連番画像と一緒に入れておくファイル。入れる場所がない出力では、出力の隣に
+    // 名前を拡張子にして置く。
+    fn extra_files(&self, _recording: &Recording) -> Result<Vec<(String, Vec<u8>)>> {
+        Ok(Vec::new())
+    }
+
+    // 書き出しを終えた後に、出力の隣にファイルを置く。
+    fn write(&self, _recording: &Recording, _out_path: &Path) -> Result<()> {
+        Ok(())
+    }
+}
+
+// コマをinnerに渡しつつ並びを覚えておき、書き終えたらrecorderに渡す。
+pub struct RecordingSink<R> {
+    inner: Box<dyn FrameSink>,
+    recorder: R,
+    recording: Recording,
+}
+
+impl<R: Recorder> RecordingSink<R> {
+    pub fn new(inner: Box<dyn FrameSink>, recorder: R) -> Self {
+        Self {
+            inner,
+            recorder,
+            recording: Recording::default(),
+        }
+    }
+
+    pub fn recording(&self) -> &Recording {
+        &self.recording
+    }
+}
+
+impl<R: Recorder> FrameSink for RecordingSink<R> {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()> {
+        if self.recording.size.is_none() {
+            self.recording.size = Some((frame.img.width(), frame.img.height()));
+        }
+        self.recording.entries.push(frame.info());
+
+        self.inner.add_frame(frame)
+    }
+
+    fn add_extra_file(&mut self, name: &str, buf: &[u8]) -> Result<bool> {
+        self.inner.add_extra_file(name, buf)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<PathBuf> {
+        let mut beside = Vec::new();
+        for (name, buf) in self.recorder.extra_files(&self.recording)? {
+            if !self.inner.add_extra_file(&name, &buf)? {
+                beside.push((name, buf));
+            }
+        }
+
+        let path = self.inner.finish()?;
+        for (name, buf) in beside {
+            fs::write(path.with_extension(name), buf)?;
+        }
+        self.recorder.write(&self.recording, &path)?;
+
+        Ok(path)
+    }
+}
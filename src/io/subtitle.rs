@@ -0,0 +1,136 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+use anyhow::Result;
+
+use super::sink::{Recorder, Recording};
+use crate::Timing;
+
+#[derive(Debug, Clone)]
+pub struct SubtitleOptions {
+    pub timing: Timing,
+    // {number}, {index}, {pref}, {count}が使える。
+    pub caption: String,
+}
+
+// 出力先の隣に、コマごとに都道府県名を出すSRTとASSを置く。
+pub struct SubtitleWriter {
+    options: SubtitleOptions,
+}
+
+impl SubtitleWriter {
+    pub fn new(options: &SubtitleOptions) -> Self {
+        Self {
+            options: options.clone(),
+        }
+    }
+
+    // イントロには字幕をつけない。途中のコマは、続く塗り終わったコマの字幕に含める。
+    fn cues(&self, recording: &Recording) -> Vec<(u64, u64, String)> {
+        let timing = &self.options.timing;
+        let mut cues = Vec::new();
+        let mut start = None;
+
+        for entry in recording
+            .entries
+            .iter()
+            .filter(|entry| entry.pref.is_some())
+        {
+            let cue_start = *start.get_or_insert(entry.start);
+            if entry.transition {
+                continue;
+            }
+
+            cues.push((
+                timing.to_ms(cue_start),
+                timing.to_ms(entry.end()),
+                entry.fill(&self.options.caption),
+            ));
+            start = None;
+        }
+
+        cues
+    }
+
+    fn srt(&self, recording: &Recording) -> Result<String> {
+        let mut srt = String::new();
+        for (idx, (start, end, text)) in self.cues(recording).into_iter().enumerate() {
+            writeln!(srt, "{}", idx + 1)?;
+            writeln!(srt, "{} --> {}", srt_time(start), srt_time(end))?;
+            writeln!(srt, "{}", text)?;
+            writeln!(srt)?;
+        }
+
+        Ok(srt)
+    }
+
+    fn ass(&self, recording: &Recording) -> Result<String> {
+        let (width, height) = recording.size()?;
+
+        let mut ass = String::new();
+        writeln!(ass, "[Script Info]")?;
+        writeln!(ass, "ScriptType: v4.00+")?;
+        writeln!(ass, "PlayResX: {}", width)?;
+        writeln!(ass, "PlayResY: {}", height)?;
+        writeln!(ass, "WrapStyle: 0")?;
+        writeln!(ass, "ScaledBorderAndShadow: yes")?;
+        writeln!(ass)?;
+        writeln!(ass, "[V4+ Styles]")?;
+        writeln!(ass, "Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding")?;
+        writeln!(
+            ass,
+            "Style: Default,Meiryo,{},&H00FFFFFF,&H000000FF,&H00000000,&H80000000,0,0,0,0,100,100,0,0,1,2,0,2,10,10,{},128",
+            (height / 12).max(8),
+            height / 24
+        )?;
+        writeln!(ass)?;
+        writeln!(ass, "[Events]")?;
+        writeln!(
+            ass,
+            "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text"
+        )?;
+        for (start, end, text) in self.cues(recording) {
+            writeln!(
+                ass,
+                "Dialogue: 0,{},{},Default,,0,0,0,,{}",
+                ass_time(start),
+                ass_time(end),
+                text.replace('\n', "\\N")
+            )?;
+        }
+
+        Ok(ass)
+    }
+}
+
+impl Recorder for SubtitleWriter {
+    fn write(&self, recording: &Recording, out_path: &Path) -> Result<()> {
+        fs::write(out_path.with_extension("srt"), self.srt(recording)?)?;
+        fs::write(out_path.with_extension("ass"), self.ass(recording)?)?;
+
+        Ok(())
+    }
+}
+
+fn srt_time(ms: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+// ASSは1/100秒単位
+fn ass_time(ms: u64) -> String {
+    let cs = ms / 10;
+    format!(
+        "{}:{:02}:{:02}.{:02}",
+        cs / 360_000,
+        cs / 6000 % 60,
+        cs / 100 % 60,
+        cs % 100
+    )
+}
@@ -0,0 +1,60 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+
+use super::png::encode_png;
+use super::sink::{create_output_file, output_path, FrameSink};
+use crate::Frame;
+
+pub struct TarSink {
+    pub tar: tar::Builder<File>,
+    pub path: PathBuf,
+    optimize_png: bool,
+}
+
+impl TarSink {
+    pub fn create(out_dir: &Path, optimize_png: bool) -> Result<Self> {
+        let path = output_path(out_dir, Some("tar"));
+        let tar_file = create_output_file(out_dir, &path)?;
+        let tar = tar::Builder::new(tar_file);
+
+        Ok(Self {
+            tar,
+            path,
+            optimize_png,
+        })
+    }
+
+    fn append(&mut self, name: &str, buf: &[u8]) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(buf.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(chrono::Local::now().timestamp() as u64);
+        self.tar.append_data(&mut header, name, buf)?;
+
+        Ok(())
+    }
+}
+
+impl FrameSink for TarSink {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()> {
+        let buf = encode_png(&frame.img, self.optimize_png)?;
+        for name in frame.file_names() {
+            self.append(&name, &buf)?;
+        }
+
+        Ok(())
+    }
+
+    fn add_extra_file(&mut self, name: &str, buf: &[u8]) -> Result<bool> {
+        self.append(name, buf)?;
+
+        Ok(true)
+    }
+
+    fn finish(self: Box<Self>) -> Result<PathBuf> {
+        self.tar.into_inner()?;
+        Ok(self.path)
+    }
+}
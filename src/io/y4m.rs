@@ -0,0 +1,117 @@
+use std::io::{stdout, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use image::{Rgb, RgbaImage};
+
+use super::sink::{create_output_file, output_path, FrameSink};
+use crate::Frame;
+
+pub struct Y4mSink {
+    writer: BufWriter<Box<dyn Write + Send>>,
+    path: PathBuf,
+    fps: u32,
+    background: Rgb<u8>,
+    size: Option<(u32, u32)>,
+}
+
+impl Y4mSink {
+    pub fn create(out_dir: &Path, fps: u32, background: Rgb<u8>) -> Result<Self> {
+        let path = output_path(out_dir, Some("y4m"));
+        let file = create_output_file(out_dir, &path)?;
+
+        Ok(Self::new(Box::new(file), path, fps, background))
+    }
+
+    pub fn stdout(fps: u32, background: Rgb<u8>) -> Self {
+        Self::new(Box::new(stdout()), PathBuf::from("-"), fps, background)
+    }
+
+    fn new(writer: Box<dyn Write + Send>, path: PathBuf, fps: u32, background: Rgb<u8>) -> Self {
+        Self {
+            writer: BufWriter::new(writer),
+            path,
+            fps: fps.max(1),
+            background,
+            size: None,
+        }
+    }
+}
+
+impl FrameSink for Y4mSink {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()> {
+        let img = frame.img.to_rgba8();
+        let size = (img.width(), img.height());
+
+        match self.size {
+            None => {
+                // 色域はBT.601のリミテッドレンジ
+                writeln!(
+                    self.writer,
+                    "YUV4MPEG2 W{} H{} F{}:1 Ip A1:1 C420jpeg XCOLORRANGE=LIMITED",
+                    size.0, size.1, self.fps
+                )?;
+                self.size = Some(size);
+            }
+            Some(s) if s != size => bail!("all frames must have the same size"),
+            _ => {}
+        }
+
+        let (y, u, v) = to_yuv420(&img, &self.background);
+        for _ in 0..frame.duration {
+            self.writer.write_all(b"FRAME\n")?;
+            self.writer.write_all(&y)?;
+            self.writer.write_all(&u)?;
+            self.writer.write_all(&v)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<PathBuf> {
+        self.writer.flush()?;
+        Ok(self.path)
+    }
+}
+
+fn to_yuv420(img: &RgbaImage, background: &Rgb<u8>) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    let (w, h) = img.dimensions();
+    let (cw, ch) = (w.div_ceil(2), h.div_ceil(2));
+
+    // 透明な部分は背景色と混ぜておく。
+    let rgb = img
+        .pixels()
+        .map(|p| {
+            let a = p.0[3] as f32 / 255.0;
+            [0, 1, 2].map(|i| p.0[i] as f32 * a + background.0[i] as f32 * (1.0 - a))
+        })
+        .collect::<Vec<_>>();
+
+    let y = rgb
+        .iter()
+        .map(|&[r, g, b]| (16.0 + (65.481 * r + 128.553 * g + 24.966 * b) / 255.0).round() as u8)
+        .collect::<Vec<_>>();
+
+    let mut u = Vec::with_capacity((cw * ch) as usize);
+    let mut v = Vec::with_capacity((cw * ch) as usize);
+    for cy in 0..ch {
+        for cx in 0..cw {
+            let mut sum = [0.0; 3];
+            let mut n = 0.0;
+            for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                let (x, y) = (cx * 2 + dx, cy * 2 + dy);
+                if x < w && y < h {
+                    let px = rgb[(y * w + x) as usize];
+                    (0..3).for_each(|i| sum[i] += px[i]);
+                    n += 1.0;
+                }
+            }
+            let [r, g, b] = sum.map(|c| c / n);
+
+            u.push((128.0 + (-37.797 * r - 74.203 * g + 112.0 * b) / 255.0).round() as u8);
+            v.push((128.0 + (112.0 * r - 93.786 * g - 18.214 * b) / 255.0).round() as u8);
+        }
+    }
+
+    (y, u, v)
+}
@@ -1,48 +1,182 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use image::DynamicImage;
-use nanoid::nanoid;
+use rayon::{ThreadPool, ThreadPoolBuilder};
 use std::{
-    fs::{self, File},
-    io::{Cursor, ErrorKind, Write},
-    path::PathBuf,
+    collections::BTreeMap,
+    fs::File,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver, Sender},
 };
-use zip::{write::SimpleFileOptions, ZipWriter};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use super::png::encode_png;
+use super::sink::{create_output_file, output_path, FrameSink};
+use crate::{Compression, Frame};
+
+#[derive(Debug, Clone)]
+pub struct ZipOptions {
+    pub compression: Compression,
+    // 256色のパレットにしてから入れる。
+    pub optimize_png: bool,
+    // 並べて圧縮する数。指定しなければCPUの数
+    pub workers: Option<usize>,
+}
+
+// PNGにして圧縮し終えた1コマ分。中身は1件だけ入ったzip
+struct Encoded {
+    file_names: Vec<String>,
+    archive: Vec<u8>,
+}
 
 pub struct ZipBuilder {
     pub zip: ZipWriter<File>,
     pub path: PathBuf,
+    options: ZipOptions,
+    pool: ThreadPool,
+    tx: Sender<(usize, Result<Encoded>)>,
+    rx: Receiver<(usize, Result<Encoded>)>,
+    // 順番が来るまで待たせておく、仕上がったコマ
+    pending: BTreeMap<usize, Result<Encoded>>,
+    sent: usize,
+    written: usize,
 }
 
 impl ZipBuilder {
-    pub fn create() -> Result<Self> {
-        let nanoid = nanoid!(4);
-        let timestamp = chrono::Local::now().format("%m%d-$H%M").to_string();
-        let path = PathBuf::from(format!("./out/{}-{}.zip", timestamp, nanoid));
-        let zip_file = match File::create_new(path.clone()) {
-            Ok(file) => file,
-            Err(e) if e.kind() == ErrorKind::NotFound => {
-                fs::create_dir("./out")?;
-                File::create_new(path.clone())?
-            }
-            Err(e) => Err(e)?,
-        };
+    pub fn create(out_dir: &Path, options: &ZipOptions) -> Result<Self> {
+        let path = output_path(out_dir, Some("zip"));
+        let zip_file = create_output_file(out_dir, &path)?;
         let zip = ZipWriter::new(zip_file);
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(options.workers.unwrap_or(0))
+            .thread_name(|n| format!("zip-encoder-{}", n))
+            .build()?;
+        let (tx, rx) = mpsc::channel();
+
+        Ok(Self {
+            zip,
+            path,
+            options: options.clone(),
+            pool,
+            tx,
+            rx,
+            pending: BTreeMap::new(),
+            sent: 0,
+            written: 0,
+        })
+    }
+
+    pub fn add_png(&mut self, img: &DynamicImage, file_dir: &str) -> Result<()> {
+        let buf = encode_png(img, self.options.optimize_png)?;
 
-        Ok(Self { zip, path })
+        self.add_file(&buf, file_dir)
     }
 
-    pub fn add_png(&mut self, img: DynamicImage, file_dir: String) -> Result<()> {
-        let mut buf = Vec::new();
-        let mut writer = Cursor::new(&mut buf);
-        img.write_to(&mut writer, image::ImageFormat::Png)?;
+    pub fn add_file(&mut self, buf: &[u8], file_dir: &str) -> Result<()> {
+        self.zip
+            .start_file(file_dir, file_options(self.options.compression))?;
 
-        let options = SimpleFileOptions::default()
-            .compression_method(zip::CompressionMethod::Bzip2)
-            .unix_permissions(0o755);
-        self.zip.start_file(file_dir, options)?;
+        self.zip.write_all(buf)?;
 
-        self.zip.write_all(buf.as_slice())?;
+        Ok(())
+    }
+
+    // 並べて圧縮していても、書き込むのは渡された順にする。
+    fn write_ready(&mut self) -> Result<()> {
+        while let Some(encoded) = self.pending.remove(&self.written) {
+            let encoded = encoded?;
+            let mut archive = ZipArchive::new(Cursor::new(encoded.archive))?;
+
+            // 圧縮済みの同じ中身を、名前だけ変えて並べる。
+            for name in encoded.file_names {
+                let file = archive.by_index_raw(0)?;
+                self.zip.raw_copy_file_rename(file, name)?;
+            }
+            self.written += 1;
+        }
 
         Ok(())
     }
+
+    // 仕上がったものを1つ受け取るまで待つ。
+    fn wait_one(&mut self) -> Result<()> {
+        let (seq, encoded) = self
+            .rx
+            .recv()
+            .map_err(|_| anyhow!("zip encoder stopped unexpectedly"))?;
+        self.pending.insert(seq, encoded);
+
+        self.write_ready()
+    }
+}
+
+impl FrameSink for ZipBuilder {
+    fn add_frame(&mut self, frame: &Frame) -> Result<()> {
+        let seq = self.sent;
+        let img = frame.img.clone();
+        let file_names = frame.file_names().collect();
+        let options = self.options.clone();
+        let tx = self.tx.clone();
+        self.pool.spawn(move || {
+            // 受け取る側がもういないなら、捨ててよい。
+            let _ = tx.send((seq, encode(&img, file_names, &options)));
+        });
+        self.sent += 1;
+
+        // 圧縮待ちのコマで、メモリがふくらみすぎないようにする。
+        while self.sent - self.written > self.pool.current_num_threads() * 2 {
+            self.wait_one()?;
+        }
+        while let Ok((seq, encoded)) = self.rx.try_recv() {
+            self.pending.insert(seq, encoded);
+        }
+
+        self.write_ready()
+    }
+
+    // 圧縮中のコマを書き終えてから、後ろに足す。
+    fn add_extra_file(&mut self, name: &str, buf: &[u8]) -> Result<bool> {
+        while self.written < self.sent {
+            self.wait_one()?;
+        }
+        self.add_file(buf, name)?;
+
+        Ok(true)
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<PathBuf> {
+        while self.written < self.sent {
+            self.wait_one()?;
+        }
+
+        self.zip.finish()?;
+        Ok(self.path)
+    }
+}
+
+fn file_options(compression: Compression) -> SimpleFileOptions {
+    let method = match compression {
+        Compression::Stored => CompressionMethod::Stored,
+        Compression::Deflate => CompressionMethod::Deflated,
+        Compression::Bzip2 => CompressionMethod::Bzip2,
+        Compression::Zstd => CompressionMethod::Zstd,
+    };
+
+    SimpleFileOptions::default()
+        .compression_method(method)
+        .unix_permissions(0o755)
+}
+
+fn encode(img: &DynamicImage, file_names: Vec<String>, options: &ZipOptions) -> Result<Encoded> {
+    let png = encode_png(img, options.optimize_png)?;
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.start_file("frame.png", file_options(options.compression))?;
+    zip.write_all(&png)?;
+    let archive = zip.finish()?.into_inner();
+
+    Ok(Encoded {
+        file_names,
+        archive,
+    })
 }
@@ -0,0 +1,205 @@
+use ab_glyph::FontArc;
+use anyhow::Result;
+use image::{Pixel, Rgba, RgbaImage};
+
+use crate::text::{blit, rasterize, text_px, Coverage};
+use crate::{LabelName, Pref, TextPosition, TextStyle};
+
+#[derive(Debug, Clone)]
+pub struct LabelOptions {
+    pub name: LabelName,
+    // {name}と{count}が使える。
+    pub text: String,
+    // positionは使わない。
+    pub style: TextStyle,
+}
+
+impl LabelOptions {
+    // 地図に載せるので、都府県はつけない。北海だけでは通じないので北海道にする。
+    pub fn name_of(&self, pref: &Pref) -> String {
+        match (self.name, pref) {
+            (LabelName::Kanji, Pref::Hokkai) => format!("{}{}", pref.as_kanji(), pref.suffix()),
+            (LabelName::Kanji, _) => pref.as_kanji(),
+            (LabelName::Kana, Pref::Hokkai) => format!("{}どう", pref.as_kana()),
+            (LabelName::Kana, _) => pref.as_kana(),
+            (LabelName::Romaji, _) => pref.as_romaji(),
+        }
+    }
+
+    pub fn text_of(&self, pref: &Pref, count: usize) -> String {
+        self.text
+            .replace("{name}", &self.name_of(pref))
+            .replace("{count}", &count.to_string())
+    }
+}
+
+// pointは、その都道府県の見た目の中心
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub point: (f32, f32),
+    // 都道府県の面積 (px)。小さいものほど先に置く。
+    pub area: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Rect {
+    x: i64,
+    y: i64,
+    w: i64,
+    h: i64,
+}
+
+impl Rect {
+    fn overlaps(&self, other: &Rect) -> bool {
+        self.x < other.x + other.w
+            && other.x < self.x + self.w
+            && self.y < other.y + other.h
+            && other.y < self.y + self.h
+    }
+
+    fn inside(&self, w: i64, h: i64) -> bool {
+        self.x >= 0 && self.y >= 0 && self.x + self.w <= w && self.y + self.h <= h
+    }
+
+    // 中心からpointへ向かう線が、枠とぶつかるところ
+    fn edge_toward(&self, point: (f32, f32)) -> (f32, f32) {
+        let (cx, cy) = (
+            self.x as f32 + self.w as f32 / 2.0,
+            self.y as f32 + self.h as f32 / 2.0,
+        );
+        let (dx, dy) = (point.0 - cx, point.1 - cy);
+        let tx = if dx != 0.0 {
+            (self.w as f32 / 2.0) / dx.abs()
+        } else {
+            f32::MAX
+        };
+        let ty = if dy != 0.0 {
+            (self.h as f32 / 2.0) / dy.abs()
+        } else {
+            f32::MAX
+        };
+        let t = tx.min(ty).min(1.0);
+
+        (cx + dx * t, cy + dy * t)
+    }
+}
+
+// 中心に置けないものは、まわりへ少しずつずらして空いているところを探し、引き出し線でつなぐ。
+fn place(labels: &[(Label, Coverage)], w: i64, h: i64, step: i64) -> Vec<Rect> {
+    let mut placed: Vec<Rect> = Vec::new();
+
+    for (label, coverage) in labels {
+        let (cw, ch) = (coverage.width as i64, coverage.height as i64);
+        let center = Rect {
+            x: label.point.0 as i64 - cw / 2,
+            y: label.point.1 as i64 - ch / 2,
+            w: cw,
+            h: ch,
+        };
+
+        // 近い順に試す。
+        let mut candidates = (-12..=12)
+            .flat_map(|dy| (-12..=12).map(move |dx| (dx * step, dy * step)))
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|(dx, dy)| dx * dx + dy * dy);
+
+        let rect = candidates
+            .into_iter()
+            .map(|(dx, dy)| Rect {
+                x: center.x + dx,
+                y: center.y + dy,
+                ..center
+            })
+            .find(|rect| rect.inside(w, h) && !placed.iter().any(|p| p.overlaps(rect)))
+            .unwrap_or(center);
+
+        placed.push(rect);
+    }
+
+    placed
+}
+
+pub fn draw_labels(
+    img: &mut RgbaImage,
+    font: &FontArc,
+    labels: &[Label],
+    style: &TextStyle,
+) -> Result<()> {
+    let px = text_px(img, style);
+
+    // 小さい都道府県ほど動かす余地がないので先に置く。
+    // 同じ大きさなら上から順にして、結果が塗った順に左右されないようにする。
+    let mut labels = labels
+        .iter()
+        .map(|label| {
+            let coverage = rasterize(
+                font,
+                &label.text,
+                px,
+                TextPosition::Center,
+                style.outline_width,
+            )?;
+            Ok((label.clone(), coverage))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    labels.sort_by(|(a, _), (b, _)| {
+        a.area
+            .cmp(&b.area)
+            .then(a.point.1.total_cmp(&b.point.1))
+            .then(a.point.0.total_cmp(&b.point.0))
+    });
+
+    let (w, h) = (img.width() as i64, img.height() as i64);
+    let rects = place(&labels, w, h, (px / 2.0).max(1.0) as i64);
+
+    let [r, g, b] = style.outline_color.0;
+    let line_color = Rgba([r, g, b, 255]);
+    for ((label, _), rect) in labels.iter().zip(&rects) {
+        let moved = !rect.overlaps(&Rect {
+            x: label.point.0 as i64,
+            y: label.point.1 as i64,
+            w: 1,
+            h: 1,
+        });
+        if moved {
+            draw_line(img, label.point, rect.edge_toward(label.point), line_color);
+            draw_dot(img, label.point, (px / 8.0).max(1.5), line_color);
+        }
+    }
+    for ((_, coverage), rect) in labels.iter().zip(&rects) {
+        blit(img, coverage, rect.x, rect.y, style);
+    }
+
+    Ok(())
+}
+
+fn draw_line(img: &mut RgbaImage, from: (f32, f32), to: (f32, f32), color: Rgba<u8>) {
+    let len = (to.0 - from.0).hypot(to.1 - from.1);
+    let steps = len.ceil().max(1.0) as usize;
+
+    for i in 0..=steps {
+        let t = i as f32 / steps as f32;
+        let (x, y) = (from.0 + (to.0 - from.0) * t, from.1 + (to.1 - from.1) * t);
+        blend_at(img, x.round() as i64, y.round() as i64, color);
+    }
+}
+
+fn draw_dot(img: &mut RgbaImage, center: (f32, f32), radius: f32, color: Rgba<u8>) {
+    let r = radius.ceil() as i64;
+    let (cx, cy) = (center.0.round() as i64, center.1.round() as i64);
+
+    for dy in -r..=r {
+        for dx in -r..=r {
+            if ((dx * dx + dy * dy) as f32) <= radius * radius {
+                blend_at(img, cx + dx, cy + dy, color);
+            }
+        }
+    }
+}
+
+fn blend_at(img: &mut RgbaImage, x: i64, y: i64, color: Rgba<u8>) {
+    if x >= 0 && y >= 0 && x < img.width() as i64 && y < img.height() as i64 {
+        img.get_pixel_mut(x as u32, y as u32).blend(&color);
+    }
+}
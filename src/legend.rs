@@ -0,0 +1,164 @@
+use ab_glyph::FontArc;
+use anyhow::Result;
+use image::{Pixel, Rgb, Rgba, RgbaImage};
+
+use crate::text::{anchor, blit, rasterize, text_px, Coverage};
+use crate::{TextPosition, TextStyle};
+
+#[derive(Debug, Clone)]
+pub struct LegendOptions {
+    // {visits}と{count}が使える。
+    pub label: String,
+    // 最後の1件のコマにだけ出す。
+    pub last_only: bool,
+    pub style: TextStyle,
+}
+
+// 色見本1つ分。visitsはその色が何回目の訪問を表すか。
+#[derive(Debug, Clone)]
+pub struct LegendRow {
+    pub color: Rgb<u8>,
+    pub visits: String,
+    // いまその色で塗られている都道府県の数
+    pub count: usize,
+}
+
+impl LegendRow {
+    pub fn label(&self, template: &str) -> String {
+        template
+            .replace("{visits}", &self.visits)
+            .replace("{count}", &self.count.to_string())
+    }
+}
+
+// 訪問回数の並びは、この数より多ければ後ろを省く。
+const LISTED_VISITS: usize = 3;
+// 説明1行の幅の上限。画像の幅に対する割合
+const MAX_LABEL_WIDTH: f32 = 0.3;
+
+// 色は訪問回数で一周するので、max_visitsまでに同じ色になる回を並べる。
+// 長くなるときは「1, 7, 13, ...」のように頭の数回だけにする。
+pub fn visits_of_color(color_index: usize, num_of_colors: usize, max_visits: usize) -> String {
+    let first = color_index + 1;
+    let visits = (first..=max_visits.max(first))
+        .step_by(num_of_colors.max(1))
+        .collect::<Vec<_>>();
+
+    let listed = visits
+        .iter()
+        .take(LISTED_VISITS)
+        .map(|v| v.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if visits.len() > LISTED_VISITS {
+        format!("{}, ...", listed)
+    } else {
+        listed
+    }
+}
+
+// 幅の上限を超える説明は、収まるまで後ろを削って「...」を付ける。
+fn rasterize_label(
+    font: &FontArc,
+    text: &str,
+    px: f32,
+    max_width: usize,
+    outline_width: u32,
+) -> Result<Coverage> {
+    let mut chars = text.chars().collect::<Vec<_>>();
+    let mut label = rasterize(font, text, px, TextPosition::TopLeft, outline_width)?;
+    while label.width > max_width && !chars.is_empty() {
+        chars.pop();
+        let text = format!("{}...", chars.iter().collect::<String>().trim_end());
+        label = rasterize(font, &text, px, TextPosition::TopLeft, outline_width)?;
+    }
+
+    Ok(label)
+}
+
+// 半透明の板の上に、色見本と説明を1行ずつ並べる。
+pub fn draw_legend(
+    img: &mut RgbaImage,
+    font: &FontArc,
+    rows: &[LegendRow],
+    options: &LegendOptions,
+) -> Result<()> {
+    let style = &options.style;
+    let px = text_px(img, style);
+    let swatch = px.round() as usize;
+    let gap = swatch / 2;
+
+    let max_width = (img.width() as f32 * MAX_LABEL_WIDTH) as usize;
+
+    let labels = rows
+        .iter()
+        .map(|row| {
+            rasterize_label(
+                font,
+                &row.label(&options.label),
+                px,
+                max_width,
+                style.outline_width,
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let row_height = labels
+        .iter()
+        .map(|label| label.height)
+        .max()
+        .unwrap_or_default()
+        .max(swatch);
+    let label_width = labels
+        .iter()
+        .map(|label| label.width)
+        .max()
+        .unwrap_or_default();
+    let width = gap * 3 + swatch + label_width;
+    let height = gap * 2 + row_height * rows.len();
+    let (x, y) = anchor(img, width, height, px, style.position);
+
+    let [r, g, b] = style.outline_color.0;
+    fill_rect(img, x, y, width, height, Rgba([r, g, b, 160]));
+
+    for (n, (row, label)) in rows.iter().zip(&labels).enumerate() {
+        let row_y = y + (gap + row_height * n) as i64;
+        let swatch_x = x + gap as i64;
+        let swatch_y = row_y + (row_height - swatch) as i64 / 2;
+
+        let [r, g, b] = style.color.0;
+        fill_rect(
+            img,
+            swatch_x,
+            swatch_y,
+            swatch,
+            swatch,
+            Rgba([r, g, b, 255]),
+        );
+        let [r, g, b] = row.color.0;
+        fill_rect(
+            img,
+            swatch_x + 1,
+            swatch_y + 1,
+            swatch.saturating_sub(2),
+            swatch.saturating_sub(2),
+            Rgba([r, g, b, 255]),
+        );
+
+        let label_x = swatch_x + (swatch + gap) as i64;
+        let label_y = row_y + (row_height - label.height) as i64 / 2;
+        blit(img, label, label_x, label_y, style);
+    }
+
+    Ok(())
+}
+
+fn fill_rect(img: &mut RgbaImage, x: i64, y: i64, w: usize, h: usize, color: Rgba<u8>) {
+    let (iw, ih) = (img.width() as i64, img.height() as i64);
+
+    for py in y.max(0)..(y + h as i64).min(ih) {
+        for px in x.max(0)..(x + w as i64).min(iw) {
+            img.get_pixel_mut(px as u32, py as u32).blend(&color);
+        }
+    }
+}
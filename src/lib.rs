@@ -1,10 +1,18 @@
 mod img;
 mod io;
+mod label;
+mod legend;
 mod loot_box;
 mod pref;
+mod render;
+mod text;
 
-pub use img::PrefImgGenerator;
+pub use img::{transition_img, Glow, Highlight, PrefImgGenerator};
 pub use io::*;
 pub use io::{input, InputData, ZipBuilder};
+pub use label::{Label, LabelOptions};
+pub use legend::{visits_of_color, LegendOptions, LegendRow};
 pub use loot_box::LootBox;
-pub use pref::{Pref, PrefDict};
+pub use pref::{BBox, Pref, PrefDict, PrefGeometries, PrefGeometry, PrefMask, PrefMasks};
+pub use render::{frame_file_name, render, Frame, FrameInfo, Frames, RenderOptions, Timing};
+pub use text::{check_glyphs, load_font, TextOptions, TextStyle};
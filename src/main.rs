@@ -1,19 +1,24 @@
 use anyhow::Result;
-use clap::Parser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
-use oyassan::{input, Config, LootBox, PrefImgGenerator, ZipBuilder};
-use ringbuf::traits::{Consumer, Observer, Producer, Split};
-use ringbuf::SharedRb;
-use rodio::{Decoder, OutputStream, Source};
-use std::collections::HashMap;
-use std::fs::File;
-use std::io::{stdin, BufReader};
-use std::sync::mpsc;
+use oyassan::{
+    input, reassemble, render, Assets, Compression, Config, Frame, Issue, IssueError, LabelName,
+    Layer, LayeredConfig, LootBox, OutputFormat, PartialConfig, RenderOptions, Resolution,
+    TextPosition, Transition,
+};
+use rodio::OutputStream;
+use std::io::{stdin, Cursor};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::mpsc::{self, RecvTimeoutError, TrySendError};
 use std::thread::Scope;
 use std::{thread, time};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-struct EndPaint;
+// おやっさんが先に塗っておけるコマの数
+const QUEUE_SIZE: usize = 8;
+// これより長く待たされたら、待っていることを表示する。
+const WAIT_MESSAGE_AFTER: time::Duration = time::Duration::from_millis(100);
 
 #[derive(Debug, Clone, Parser)]
 struct Args {
@@ -24,10 +29,306 @@ struct Args {
     use_config: bool,
 
     #[arg(long)]
-    input_path: Option<String>,
+    input_path: Option<PathBuf>,
+
+    #[arg(long)]
+    headless: bool,
+
+    #[arg(long, value_enum)]
+    resolution: Option<Resolution>,
+
+    #[arg(long = "ignore", value_enum)]
+    ignore_issues: Vec<Issue>,
+
+    #[arg(long, conflicts_with = "no_sound")]
+    sound: bool,
+
+    #[arg(long)]
+    no_sound: bool,
+
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+
+    #[arg(long, value_enum)]
+    output: Option<OutputFormat>,
+
+    #[arg(long)]
+    encode_workers: Option<usize>,
+
+    #[arg(long, value_enum)]
+    compression: Option<Compression>,
+
+    #[arg(long, conflicts_with = "no_optimize_png")]
+    optimize_png: bool,
+
+    #[arg(long)]
+    no_optimize_png: bool,
+
+    #[arg(long, conflicts_with = "no_delta")]
+    delta: bool,
+
+    #[arg(long)]
+    no_delta: bool,
+
+    #[arg(long)]
+    delta_keyframe_interval: Option<u32>,
+
+    #[arg(long)]
+    fps: Option<u32>,
+
+    #[arg(long)]
+    entry_secs: Option<f64>,
+
+    #[arg(long)]
+    hold_last_secs: Option<f64>,
+
+    #[arg(long)]
+    intro_secs: Option<f64>,
+
+    #[arg(long, value_enum)]
+    transition: Option<Transition>,
+
+    #[arg(long)]
+    transition_frames: Option<u32>,
+
+    #[arg(long)]
+    loop_count: Option<u16>,
+
+    #[arg(long)]
+    background: Option<String>,
+
+    #[arg(long, conflicts_with = "no_stdout")]
+    stdout: bool,
+
+    #[arg(long)]
+    no_stdout: bool,
+
+    #[arg(long, conflicts_with = "no_exo")]
+    exo: bool,
+
+    #[arg(long)]
+    no_exo: bool,
+
+    #[arg(long)]
+    exo_layer: Option<u32>,
+
+    #[arg(long, allow_negative_numbers = true)]
+    exo_x: Option<f64>,
+
+    #[arg(long, allow_negative_numbers = true)]
+    exo_y: Option<f64>,
+
+    #[arg(long)]
+    exo_template: Option<PathBuf>,
+
+    #[arg(long, conflicts_with = "no_subtitles")]
+    subtitles: bool,
+
+    #[arg(long)]
+    no_subtitles: bool,
+
+    #[arg(long)]
+    caption: Option<String>,
+
+    #[arg(long, conflicts_with = "no_edl")]
+    edl: bool,
+
+    #[arg(long)]
+    no_edl: bool,
+
+    #[arg(long, conflicts_with = "no_fcpxml")]
+    fcpxml: bool,
+
+    #[arg(long)]
+    no_fcpxml: bool,
+
+    #[arg(long, conflicts_with = "no_highlight")]
+    highlight: bool,
+
+    #[arg(long)]
+    no_highlight: bool,
+
+    #[arg(long)]
+    highlight_color: Option<String>,
+
+    #[arg(long)]
+    highlight_width: Option<u32>,
+
+    #[arg(long)]
+    font: Option<PathBuf>,
+
+    #[arg(long)]
+    text_caption: Option<String>,
+
+    #[arg(long)]
+    text_title: Option<String>,
+
+    #[arg(long)]
+    text_footer: Option<String>,
+
+    #[arg(long, value_enum)]
+    text_caption_position: Option<TextPosition>,
+
+    #[arg(long, value_enum)]
+    text_title_position: Option<TextPosition>,
+
+    #[arg(long, value_enum)]
+    text_footer_position: Option<TextPosition>,
+
+    #[arg(long)]
+    text_caption_size: Option<f64>,
+
+    #[arg(long)]
+    text_title_size: Option<f64>,
+
+    #[arg(long)]
+    text_footer_size: Option<f64>,
+
+    #[arg(long)]
+    text_color: Option<String>,
+
+    #[arg(long)]
+    text_outline_color: Option<String>,
+
+    #[arg(long)]
+    text_outline_width: Option<u32>,
+
+    #[arg(long, conflicts_with = "no_legend")]
+    legend: bool,
+
+    #[arg(long)]
+    no_legend: bool,
+
+    #[arg(long, conflicts_with = "no_legend_last_only")]
+    legend_last_only: bool,
+
+    #[arg(long)]
+    no_legend_last_only: bool,
+
+    #[arg(long, value_enum)]
+    legend_position: Option<TextPosition>,
+
+    #[arg(long)]
+    legend_size: Option<f64>,
+
+    #[arg(long)]
+    legend_label: Option<String>,
+
+    #[arg(long, conflicts_with = "no_labels")]
+    labels: bool,
+
+    #[arg(long)]
+    no_labels: bool,
+
+    #[arg(long, value_enum)]
+    label_name: Option<LabelName>,
+
+    #[arg(long)]
+    label_text: Option<String>,
+
+    #[arg(long)]
+    label_size: Option<f64>,
+
+    #[arg(long)]
+    show_config: bool,
+
+    #[arg(long)]
+    data_dir: Option<PathBuf>,
+
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+enum Command {
+    // --deltaで書き出した差分の連番画像を、丸ごとの連番画像に戻す。
+    Reassemble {
+        // zip, tarまたはフォルダ。中のdelta.jsonを読む。
+        path: PathBuf,
+
+        #[arg(long)]
+        out_dir: Option<PathBuf>,
+    },
+}
+
+impl Args {
+    fn as_partial(&self) -> PartialConfig {
+        PartialConfig {
+            input_path: self.input_path.clone(),
+            play_notification_sound: switch(self.sound, self.no_sound),
+            resolution: self.resolution.clone(),
+            ignore_issues: (!self.ignore_issues.is_empty())
+                .then(|| self.ignore_issues.iter().cloned().collect()),
+            out_dir: self.out_dir.clone(),
+            output: self.output.clone(),
+            encode_workers: self.encode_workers.map(Some),
+            compression: self.compression,
+            optimize_png: switch(self.optimize_png, self.no_optimize_png),
+            delta: switch(self.delta, self.no_delta),
+            delta_keyframe_interval: self.delta_keyframe_interval,
+            fps: self.fps,
+            entry_secs: self.entry_secs,
+            hold_last_secs: self.hold_last_secs,
+            intro_secs: self.intro_secs,
+            transition: self.transition,
+            transition_frames: self.transition_frames,
+            loop_count: self.loop_count,
+            background: self.background.clone(),
+            stdout: switch(self.stdout, self.no_stdout),
+            exo: switch(self.exo, self.no_exo),
+            exo_layer: self.exo_layer,
+            exo_x: self.exo_x,
+            exo_y: self.exo_y,
+            exo_template: self.exo_template.clone().map(Some),
+            subtitles: switch(self.subtitles, self.no_subtitles),
+            caption: self.caption.clone(),
+            edl: switch(self.edl, self.no_edl),
+            fcpxml: switch(self.fcpxml, self.no_fcpxml),
+            highlight: switch(self.highlight, self.no_highlight),
+            highlight_color: self.highlight_color.clone(),
+            highlight_width: self.highlight_width,
+            font: self.font.clone().map(Some),
+            text_caption: self.text_caption.clone().map(Some),
+            text_title: self.text_title.clone().map(Some),
+            text_footer: self.text_footer.clone().map(Some),
+            text_caption_position: self.text_caption_position,
+            text_title_position: self.text_title_position,
+            text_footer_position: self.text_footer_position,
+            text_caption_size: self.text_caption_size,
+            text_title_size: self.text_title_size,
+            text_footer_size: self.text_footer_size,
+            text_color: self.text_color.clone(),
+            text_outline_color: self.text_outline_color.clone(),
+            text_outline_width: self.text_outline_width,
+            legend: switch(self.legend, self.no_legend),
+            legend_last_only: switch(self.legend_last_only, self.no_legend_last_only),
+            legend_position: self.legend_position,
+            legend_size: self.legend_size,
+            legend_label: self.legend_label.clone(),
+            labels: switch(self.labels, self.no_labels),
+            label_name: self.label_name,
+            label_text: self.label_text.clone(),
+            label_size: self.label_size,
+        }
+    }
+}
+
+// --xと--no-xの組。どちらもなければ、設定ファイルなどの値をそのまま使う。
+fn switch(on: bool, off: bool) -> Option<bool> {
+    if on {
+        Some(true)
+    } else if off {
+        Some(false)
+    } else {
+        None
+    }
 }
 
-static NOTICE_SOUND: &str = "./data/notice.mp3";
+const EXIT_FAILURE: u8 = 1;
+const EXIT_INPUT_ISSUE: u8 = 3;
 
 fn indicator(prefs_len: usize) -> Result<(ProgressBar, ProgressBar)> {
     let style = ProgressStyle::default_bar()
@@ -49,23 +350,18 @@ fn indicator(prefs_len: usize) -> Result<(ProgressBar, ProgressBar)> {
     Ok((gen_bar, save_bar))
 }
 
-fn paint(config: &Config) -> Result<()> {
-    let mut zip = ZipBuilder::create().expect("failed to create zip.");
-    let out_path = zip.path.clone();
+fn paint(config: &Config, assets: &Assets) -> Result<PathBuf> {
+    let input = input(config, assets)?;
+    let frames = render(input, &RenderOptions::from_config(config, assets)?)?;
+    let mut sink = config.create_sink()?;
 
-    thread::scope(|s: &Scope<'_, '_>| {
-        let input = input(config).expect("failed to get input");
-        let (gen_bar, save_bar) =
-            indicator(input.prefs.len()).expect("failed to create progress bar");
-
-        let mut generator = PrefImgGenerator::new(config.resolution.as_size());
-
-        let zip_queue = SharedRb::new(8);
-        let (mut tx, mut rx) = zip_queue.split();
-        let (state_tx, state_rx) = mpsc::channel();
+    // おやっさんが塗ったコマを、ボブが順に書き出す。
+    // 送る側が閉じたら塗り終わり。どちらかが失敗したら、もう片方も止まる。
+    let out_path = thread::scope(|s: &Scope<'_, '_>| {
+        let (gen_bar, save_bar) = indicator(frames.len()).expect("failed to create progress bar");
+        let (tx, rx) = mpsc::sync_channel::<Result<Frame>>(QUEUE_SIZE);
 
         s.spawn(move || {
-            let mut num_of_pref_map = HashMap::new();
             let mut save_msg = LootBox::new(vec![
                 String::from("おやっさんは丁寧に塗っている。"),
                 String::from("おやっさんはふちを気をつけて塗っている。"),
@@ -74,30 +370,30 @@ fn paint(config: &Config) -> Result<()> {
                 String::from("おやっさんは塗りむらが出ないようにしている。"),
             ]);
 
-            for (idx, pref) in input.prefs.into_iter().enumerate() {
+            for frame in frames {
                 gen_bar.inc(1);
                 gen_bar.set_message(save_msg.roll());
+                let failed = frame.is_err();
 
-                let num_of_pref = *num_of_pref_map.get(&pref).unwrap_or(&0);
-                num_of_pref_map.insert(pref.clone(), num_of_pref + 1);
-
-                let tint_color = input.colors[num_of_pref % input.colors.len()];
-                generator.overlay(&pref, &tint_color);
+                let sent = match tx.try_send(frame) {
+                    Ok(()) => Ok(()),
+                    Err(TrySendError::Full(frame)) => {
+                        gen_bar.set_message("おやっさんはボブの仕事を待っている。");
+                        tx.send(frame).map_err(|_| ())
+                    }
+                    Err(TrySendError::Disconnected(_)) => Err(()),
+                };
 
-                while tx.is_full() {
-                    gen_bar.set_message("おやっさんはボブの仕事を待っている。");
+                // ボブが手を止めたか、塗るのに失敗したら終わり
+                if sent.is_err() || failed {
+                    break;
                 }
-
-                tx.try_push((generator.get_img(), format!("{:06}.png", idx)))
-                    .expect("failed to push to queue");
             }
 
-            state_tx.send(EndPaint).expect("failed to send state");
             gen_bar.finish();
         });
 
-        s.spawn(move || {
-            let mut done_generated = false;
+        let saver = s.spawn(move || -> Result<PathBuf> {
             let mut gen_msg = LootBox::new(vec![
                 String::from("ボブはzipファイルに画像をそっとしまっている。"),
                 String::from("ボブはおやっさんの作品を大事にzipファイルに入れている。"),
@@ -108,68 +404,163 @@ fn paint(config: &Config) -> Result<()> {
             ]);
 
             loop {
-                save_bar.set_message(gen_msg.roll());
-                save_bar.inc(1);
-
-                if state_rx.try_recv().is_ok() {
-                    done_generated = true;
-                }
-
-                if done_generated && rx.is_empty() {
-                    break;
-                }
-                let begin_wait = time::Instant::now();
-
-                while rx.is_empty() {
-                    let wait_elapsed_millis = begin_wait.elapsed().as_millis();
-                    if wait_elapsed_millis > 100 {
+                let frame = match rx.recv_timeout(WAIT_MESSAGE_AFTER) {
+                    Ok(frame) => frame,
+                    Err(RecvTimeoutError::Timeout) => {
                         save_bar.set_message("ボブはおやっさんの作業を待っている...。");
+                        match rx.recv() {
+                            Ok(frame) => frame,
+                            Err(_) => break,
+                        }
                     }
-                }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
 
-                let (img, file_dir) = rx.try_pop().expect("failed to pop from queue");
-                zip.add_png(img, file_dir)
-                    .expect("failed to add png to zip.");
+                sink.add_frame(&frame?)?;
+                save_bar.inc(1);
+                save_bar.set_message(gen_msg.roll());
             }
 
-            zip.zip.finish().expect("failed to finish zip");
+            let out_path = sink.finish()?;
             save_bar.finish();
+
+            Ok(out_path)
         });
-    });
 
-    println!(
-        "おやっさん「あんたの依頼品は`{}`に置いといたからな。」",
-        out_path.to_str().expect("failed to convert path to str")
-    );
+        saver.join().expect("failed to join saver")
+    })?;
+
+    Ok(out_path)
+}
+
+fn play_notification_sound(assets: &Assets) -> Result<()> {
+    let (_stream, stream_handle) = OutputStream::try_default()?;
+    let file = Cursor::new(assets.notice_sound()?);
+    let sink = stream_handle.play_once(file)?;
+    sink.sleep_until_end();
 
     Ok(())
 }
 
-fn main() -> Result<()> {
-    let args = Args::parse();
+fn resolve_config(args: &Args, assets: &Assets) -> Result<LayeredConfig> {
+    let mut layered = LayeredConfig::default();
+
+    if Config::exist_config_file(assets) {
+        layered.apply(Layer::File, PartialConfig::from_file(assets)?);
+    }
+    layered.apply(Layer::Env, PartialConfig::from_env()?);
+
+    if !args.headless && !args.show_config {
+        let wizard = PartialConfig::wizard(
+            assets,
+            args.advanced,
+            args.use_config,
+            args.input_path.clone(),
+        )?;
+        layered.apply(Layer::Wizard, wizard);
+    }
+    layered.apply(Layer::Cli, args.as_partial());
+
+    Ok(layered)
+}
+
+fn run(args: &Args) -> Result<()> {
+    if let Some(Command::Reassemble { path, out_dir }) = &args.command {
+        let out_dir = out_dir
+            .clone()
+            .or_else(|| path.parent().map(Path::to_path_buf))
+            .unwrap_or_default();
+        let out_path = reassemble(path, &out_dir)?;
+
+        if args.headless {
+            println!("{}", out_path.display());
+        } else {
+            println!(
+                "おやっさん「組み直したものは`{}`に置いといたからな。」",
+                out_path.display()
+            );
+        }
+        return Ok(());
+    }
+
+    let assets = Assets::locate(args.data_dir.clone(), args.config.clone())?;
+    let layered = resolve_config(args, &assets)?;
+
+    if args.show_config {
+        println!(
+            "data_dir = {}",
+            assets
+                .data_dir()
+                .map(|dir| dir.display().to_string())
+                .unwrap_or_else(|| String::from("(embedded)"))
+        );
+        println!(
+            "config   = {}",
+            assets
+                .config_path()
+                .unwrap_or(assets.config_save_path())
+                .display()
+        );
+        print!("{}", layered);
+        return Ok(());
+    }
 
-    let mut config = Config::wizard(args.advanced, args.use_config)?;
-    if args.use_config {
-        let c = Config::from_file()?;
-        config = Config {
-            input_path: config.input_path,
-            ..c
-        };
+    let config = layered.config;
+    if config.input_path.as_os_str().is_empty() {
+        Args::command()
+            .error(
+                ErrorKind::MissingRequiredArgument,
+                "no input path: pass --input-path, set OYASSAN_INPUT_PATH, or put `input_path` in the config file",
+            )
+            .exit();
     }
 
-    paint(&config)?;
+    let out_path = paint(&config, &assets)?;
+
+    // 標準出力に映像を流しているときは、メッセージで汚さないようにする。
+    if config.stdout {
+        eprintln!("おやっさん「あんたの依頼品は流し終わったぞ。」");
+    } else if args.headless {
+        println!("{}", out_path.display());
+    } else {
+        println!(
+            "おやっさん「あんたの依頼品は`{}`に置いといたからな。」",
+            out_path.display()
+        );
+    }
 
     if config.play_notification_sound {
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        let file = File::open(NOTICE_SOUND)?;
-        let file = BufReader::new(file);
-        let source = Decoder::new(file).unwrap();
-        stream_handle.play_raw(source.convert_samples())?;
+        if let Err(e) = play_notification_sound(&assets) {
+            eprintln!("おやっさん「すまん、ベルが鳴らなかった。」 ({})", e);
+        }
     }
 
-    println!("閉じるにはEnterを押してください。");
-    let mut _buf = String::new();
-    stdin().read_line(&mut _buf)?;
+    if !args.headless {
+        if config.stdout {
+            eprintln!("閉じるにはEnterを押してください。");
+        } else {
+            println!("閉じるにはEnterを押してください。");
+        }
+        let mut _buf = String::new();
+        stdin().read_line(&mut _buf)?;
+    }
 
     Ok(())
 }
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+
+            if e.downcast_ref::<IssueError>().is_some() {
+                ExitCode::from(EXIT_INPUT_ISSUE)
+            } else {
+                ExitCode::from(EXIT_FAILURE)
+            }
+        }
+    }
+}
@@ -0,0 +1,62 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::{anyhow, Result};
+
+// キーごとに1回だけ作って使い回す。clone同士で中身を共有する。
+#[derive(Debug)]
+pub struct SharedCache<K, V> {
+    entries: Arc<Mutex<HashMap<K, Arc<V>>>>,
+}
+
+impl<K, V> Clone for SharedCache<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+        }
+    }
+}
+
+impl<K, V> Default for SharedCache<K, V> {
+    fn default() -> Self {
+        Self {
+            entries: Arc::default(),
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> SharedCache<K, V> {
+    // 作っている間はロックを外すので、同じキーを同時に作ることはある。先に入ったほうが残る。
+    pub fn get_or_try_insert_with(
+        &self,
+        key: K,
+        make: impl FnOnce() -> Result<V>,
+    ) -> Result<Arc<V>> {
+        if let Some(value) = self.lock()?.get(&key) {
+            return Ok(value.clone());
+        }
+
+        let value = Arc::new(make()?);
+        Ok(self.lock()?.entry(key).or_insert(value).clone())
+    }
+
+    pub fn contains(&self, key: &K) -> Result<bool> {
+        Ok(self.lock()?.contains_key(key))
+    }
+
+    pub fn extend(&self, values: impl IntoIterator<Item = (K, V)>) -> Result<()> {
+        let values = values
+            .into_iter()
+            .map(|(key, value)| (key, Arc::new(value)));
+        self.lock()?.extend(values);
+
+        Ok(())
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, HashMap<K, Arc<V>>>> {
+        self.entries
+            .lock()
+            .map_err(|_| anyhow!("cache is poisoned"))
+    }
+}
@@ -1,15 +1,12 @@
-use std::path::Path;
-
 use anyhow::Result;
 
-const DICT_PATH: &str = "./data/pref.csv";
+use crate::Assets;
 
 pub struct Dict(pub Vec<Vec<String>>);
 
 impl Dict {
-    pub fn load_from_csv() -> Result<Self> {
-        let pref_csv_path = Path::new(DICT_PATH);
-        let raw_dict = std::fs::read_to_string(pref_csv_path)?;
+    pub fn load_from_csv(assets: &Assets) -> Result<Self> {
+        let raw_dict = assets.dict()?;
 
         let pref_dict = raw_dict
             .split("\n")
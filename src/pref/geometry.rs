@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+
+use super::cache::SharedCache;
+use super::mask::{PrefMask, PrefMasks};
+use super::name::Pref;
+
+// maskのうち、この濃さより上を都道府県の内側とみなす。
+const INSIDE_ALPHA: u8 = 127;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BBox {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+// data/mapsの透明度から求めた、ある解像度での都道府県の形の情報。座標はpx。
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefGeometry {
+    pub bbox: BBox,
+    pub area: usize,
+    pub centroid: (f32, f32),
+    // 形の内側で、ふちから一番遠い点。重心と違って必ず内側にあるので、名前を置くのに使う。
+    pub label_point: (f32, f32),
+}
+
+impl PrefGeometry {
+    pub fn from_mask(mask: &PrefMask) -> Self {
+        // ふちまでの距離を正しく測れるよう、まわりに1pxの外側を足して考える。
+        let (w, h) = (mask.width as usize + 2, mask.height as usize + 2);
+        let mut inside = vec![false; w * h];
+        for (y, row) in mask.rows().enumerate() {
+            for (x, &a) in row.iter().enumerate() {
+                inside[(y + 1) * w + x + 1] = a > INSIDE_ALPHA;
+            }
+        }
+        // 切り詰める前の、地図全体での座標にする。足した外側には使わない。
+        let to_map = |idx: usize| ((idx % w) as u32 + mask.x - 1, (idx / w) as u32 + mask.y - 1);
+        let center_of = |idx: usize| {
+            (
+                (idx % w) as f32 - 0.5 + mask.x as f32,
+                (idx / w) as f32 - 0.5 + mask.y as f32,
+            )
+        };
+
+        let mut area = 0;
+        let mut sum = (0.0, 0.0);
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+        for (idx, _) in inside.iter().enumerate().filter(|(_, &i)| i) {
+            let (x, y) = to_map(idx);
+            area += 1;
+            sum.0 += x as f64 + 0.5;
+            sum.1 += y as f64 + 0.5;
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+
+        if area == 0 {
+            let center = (
+                mask.x as f32 + mask.width as f32 / 2.0,
+                mask.y as f32 + mask.height as f32 / 2.0,
+            );
+            return Self {
+                bbox: BBox::default(),
+                area,
+                centroid: center,
+                label_point: center,
+            };
+        }
+
+        let centroid = ((sum.0 / area as f64) as f32, (sum.1 / area as f64) as f32);
+
+        // ふちからの距離が一番大きい点。同じなら重心に近いほうにする。
+        let outside = inside.iter().map(|i| !i).collect::<Vec<_>>();
+        let depth = chamfer_distance(&outside, w, h);
+        let distance_to_centroid = |idx: usize| {
+            let (x, y) = center_of(idx);
+            (x - centroid.0).powi(2) + (y - centroid.1).powi(2)
+        };
+        let deepest = (0..depth.len())
+            .max_by(|&a, &b| {
+                depth[a]
+                    .total_cmp(&depth[b])
+                    .then(distance_to_centroid(b).total_cmp(&distance_to_centroid(a)))
+            })
+            .unwrap_or_default();
+
+        Self {
+            bbox: BBox {
+                x: min_x,
+                y: min_y,
+                width: max_x - min_x + 1,
+                height: max_y - min_y + 1,
+            },
+            area,
+            centroid,
+            label_point: center_of(deepest),
+        }
+    }
+}
+
+// 解像度ごとに1回だけ求めて使い回す。
+#[derive(Debug, Clone)]
+pub struct PrefGeometries {
+    masks: PrefMasks,
+    cache: SharedCache<(Pref, u32), PrefGeometry>,
+}
+
+impl PrefGeometries {
+    pub fn new(masks: &PrefMasks) -> Self {
+        Self {
+            masks: masks.clone(),
+            cache: SharedCache::default(),
+        }
+    }
+
+    pub fn get(&self, pref: &Pref, size: u32) -> Result<Arc<PrefGeometry>> {
+        self.cache.get_or_try_insert_with((pref.clone(), size), || {
+            let mask = self.masks.get(pref, size)?;
+            Ok(PrefGeometry::from_mask(&mask))
+        })
+    }
+}
+
+// maskの中は0、外はmaskまでのおおよその距離 (px)。3-4の面取り距離で近似する。
+pub(crate) fn chamfer_distance(mask: &[bool], w: usize, h: usize) -> Vec<f32> {
+    const INF: u32 = u32::MAX / 2;
+    let mut d = mask
+        .iter()
+        .map(|&m| if m { 0 } else { INF })
+        .collect::<Vec<u32>>();
+
+    for y in 0..h {
+        for x in 0..w {
+            let i = y * w + x;
+            let mut v = d[i];
+            if x > 0 {
+                v = v.min(d[i - 1] + 3);
+            }
+            if y > 0 {
+                v = v.min(d[i - w] + 3);
+                if x > 0 {
+                    v = v.min(d[i - w - 1] + 4);
+                }
+                if x + 1 < w {
+                    v = v.min(d[i - w + 1] + 4);
+                }
+            }
+            d[i] = v;
+        }
+    }
+    for y in (0..h).rev() {
+        for x in (0..w).rev() {
+            let i = y * w + x;
+            let mut v = d[i];
+            if x + 1 < w {
+                v = v.min(d[i + 1] + 3);
+            }
+            if y + 1 < h {
+                v = v.min(d[i + w] + 3);
+                if x + 1 < w {
+                    v = v.min(d[i + w + 1] + 4);
+                }
+                if x > 0 {
+                    v = v.min(d[i + w - 1] + 4);
+                }
+            }
+            d[i] = v;
+        }
+    }
+
+    d.into_iter().map(|v| v as f32 / 3.0).collect()
+}
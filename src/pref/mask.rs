@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use super::cache::SharedCache;
+use super::name::Pref;
+use crate::Assets;
+
+// 都道府県の形を、透明度だけにして、透明でない範囲に切り詰めたもの。
+// (x, y)は地図全体の中での左上の位置
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrefMask {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub alpha: Vec<u8>,
+}
+
+impl PrefMask {
+    pub fn load(assets: &Assets, pref: &Pref, size: u32) -> Result<Self> {
+        let img = assets
+            .pref_img(pref)?
+            .resize(size, size, image::imageops::FilterType::CatmullRom)
+            .to_rgba8();
+        let (w, h) = img.dimensions();
+
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (w, h, 0, 0);
+        for (x, y, _) in img.enumerate_pixels().filter(|(_, _, p)| p.0[3] > 0) {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+        if min_x > max_x {
+            return Ok(Self::default());
+        }
+
+        let (width, height) = (max_x - min_x + 1, max_y - min_y + 1);
+        let alpha = (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+            .map(|(x, y)| img.get_pixel(x, y).0[3])
+            .collect();
+
+        Ok(Self {
+            x: min_x,
+            y: min_y,
+            width,
+            height,
+            alpha,
+        })
+    }
+
+    // 地図全体の座標で引く。範囲の外は透明
+    pub fn get(&self, x: u32, y: u32) -> u8 {
+        if x < self.x || y < self.y || x >= self.x + self.width || y >= self.y + self.height {
+            return 0;
+        }
+
+        self.alpha[((y - self.y) * self.width + (x - self.x)) as usize]
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.alpha.chunks(self.width.max(1) as usize)
+    }
+}
+
+// 読み込みと縮小は都道府県と解像度ごとに1回だけにする。
+#[derive(Debug, Clone)]
+pub struct PrefMasks {
+    assets: Assets,
+    cache: SharedCache<(Pref, u32), PrefMask>,
+}
+
+impl PrefMasks {
+    pub fn new(assets: &Assets) -> Self {
+        Self {
+            assets: assets.clone(),
+            cache: SharedCache::default(),
+        }
+    }
+
+    pub fn get(&self, pref: &Pref, size: u32) -> Result<Arc<PrefMask>> {
+        self.cache.get_or_try_insert_with((pref.clone(), size), || {
+            PrefMask::load(&self.assets, pref, size)
+        })
+    }
+
+    // まだ読んでいないものを、まとめて並列に読んでおく。
+    pub fn preload(&self, prefs: &[Pref], size: u32) -> Result<()> {
+        let mut missing = Vec::new();
+        for pref in prefs {
+            if !self.cache.contains(&(pref.clone(), size))? {
+                missing.push(pref.clone());
+            }
+        }
+        missing.sort_by_key(|pref| pref.as_key());
+        missing.dedup();
+
+        let loaded = missing
+            .into_par_iter()
+            .map(|pref| {
+                let mask = PrefMask::load(&self.assets, &pref, size)?;
+                Ok(((pref, size), mask))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        self.cache.extend(loaded)
+    }
+}
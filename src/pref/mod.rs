@@ -1,5 +1,11 @@
+mod cache;
 mod dict;
+mod geometry;
+mod mask;
 mod name;
 
 pub use dict::Dict as PrefDict;
+pub(crate) use geometry::chamfer_distance;
+pub use geometry::{BBox, PrefGeometries, PrefGeometry};
+pub use mask::{PrefMask, PrefMasks};
 pub use name::Pref;
@@ -255,6 +255,110 @@ impl Pref {
         }
     }
 
+    pub fn as_kana(&self) -> String {
+        match self {
+            Self::Aichi => String::from("あいち"),
+            Self::Akita => String::from("あきた"),
+            Self::Aomori => String::from("あおもり"),
+            Self::Chiba => String::from("ちば"),
+            Self::Ehime => String::from("えひめ"),
+            Self::Fukui => String::from("ふくい"),
+            Self::Fukuoka => String::from("ふくおか"),
+            Self::Fukushima => String::from("ふくしま"),
+            Self::Gifu => String::from("ぎふ"),
+            Self::Gumma => String::from("ぐんま"),
+            Self::Hiroshima => String::from("ひろしま"),
+            Self::Okayama => String::from("おかやま"),
+            Self::Hokkai => String::from("ほっかい"),
+            Self::Hyogo => String::from("ひょうご"),
+            Self::Ibaraki => String::from("いばらき"),
+            Self::Ishikawa => String::from("いしかわ"),
+            Self::Iwate => String::from("いわて"),
+            Self::Kagawa => String::from("かがわ"),
+            Self::Kagoshima => String::from("かごしま"),
+            Self::Kanagawa => String::from("かながわ"),
+            Self::Kochi => String::from("こうち"),
+            Self::Kumamoto => String::from("くまもと"),
+            Self::Kyoto => String::from("きょうと"),
+            Self::Mie => String::from("みえ"),
+            Self::Miyagi => String::from("みやぎ"),
+            Self::Miyazaki => String::from("みやざき"),
+            Self::Nagano => String::from("ながの"),
+            Self::Nagasaki => String::from("ながさき"),
+            Self::Nara => String::from("なら"),
+            Self::Niigata => String::from("にいがた"),
+            Self::Oita => String::from("おおいた"),
+            Self::Okinawa => String::from("おきなわ"),
+            Self::Osaka => String::from("おおさか"),
+            Self::Saga => String::from("さが"),
+            Self::Saitama => String::from("さいたま"),
+            Self::Shiga => String::from("しが"),
+            Self::Shimane => String::from("しまね"),
+            Self::Shizuoka => String::from("しずおか"),
+            Self::Tochigi => String::from("とちぎ"),
+            Self::Tokushima => String::from("とくしま"),
+            Self::Tokyo => String::from("とうきょう"),
+            Self::Tottori => String::from("とっとり"),
+            Self::Toyama => String::from("とやま"),
+            Self::Wakayama => String::from("わかやま"),
+            Self::Yamagata => String::from("やまがた"),
+            Self::Yamaguchi => String::from("やまぐち"),
+            Self::Yamanashi => String::from("やまなし"),
+        }
+    }
+
+    pub fn as_romaji(&self) -> String {
+        match self {
+            Self::Aichi => String::from("Aichi"),
+            Self::Akita => String::from("Akita"),
+            Self::Aomori => String::from("Aomori"),
+            Self::Chiba => String::from("Chiba"),
+            Self::Ehime => String::from("Ehime"),
+            Self::Fukui => String::from("Fukui"),
+            Self::Fukuoka => String::from("Fukuoka"),
+            Self::Fukushima => String::from("Fukushima"),
+            Self::Gifu => String::from("Gifu"),
+            Self::Gumma => String::from("Gunma"),
+            Self::Hiroshima => String::from("Hiroshima"),
+            Self::Okayama => String::from("Okayama"),
+            Self::Hokkai => String::from("Hokkaido"),
+            Self::Hyogo => String::from("Hyogo"),
+            Self::Ibaraki => String::from("Ibaraki"),
+            Self::Ishikawa => String::from("Ishikawa"),
+            Self::Iwate => String::from("Iwate"),
+            Self::Kagawa => String::from("Kagawa"),
+            Self::Kagoshima => String::from("Kagoshima"),
+            Self::Kanagawa => String::from("Kanagawa"),
+            Self::Kochi => String::from("Kochi"),
+            Self::Kumamoto => String::from("Kumamoto"),
+            Self::Kyoto => String::from("Kyoto"),
+            Self::Mie => String::from("Mie"),
+            Self::Miyagi => String::from("Miyagi"),
+            Self::Miyazaki => String::from("Miyazaki"),
+            Self::Nagano => String::from("Nagano"),
+            Self::Nagasaki => String::from("Nagasaki"),
+            Self::Nara => String::from("Nara"),
+            Self::Niigata => String::from("Niigata"),
+            Self::Oita => String::from("Oita"),
+            Self::Okinawa => String::from("Okinawa"),
+            Self::Osaka => String::from("Osaka"),
+            Self::Saga => String::from("Saga"),
+            Self::Saitama => String::from("Saitama"),
+            Self::Shiga => String::from("Shiga"),
+            Self::Shimane => String::from("Shimane"),
+            Self::Shizuoka => String::from("Shizuoka"),
+            Self::Tochigi => String::from("Tochigi"),
+            Self::Tokushima => String::from("Tokushima"),
+            Self::Tokyo => String::from("Tokyo"),
+            Self::Tottori => String::from("Tottori"),
+            Self::Toyama => String::from("Toyama"),
+            Self::Wakayama => String::from("Wakayama"),
+            Self::Yamagata => String::from("Yamagata"),
+            Self::Yamaguchi => String::from("Yamaguchi"),
+            Self::Yamanashi => String::from("Yamanashi"),
+        }
+    }
+
     pub fn suffix(&self) -> String {
         match self {
             Pref::Kyoto | Pref::Osaka => String::from("府"),
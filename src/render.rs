@@ -0,0 +1,504 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::vec;
+
+use anyhow::{anyhow, bail, ensure, Result};
+use image::{DynamicImage, Rgb};
+
+use crate::img::{transition_img, with_glow, Glow};
+use crate::{
+    check_glyphs, load_font, visits_of_color, Assets, Config, Highlight, InputData, Label,
+    LabelOptions, LegendOptions, LegendRow, Pref, PrefImgGenerator, Resolution, TextOptions,
+    Transition,
+};
+
+// 長さはすべてfps基準のフレーム数に直して扱う。
+#[derive(Debug, Clone)]
+pub struct Timing {
+    pub fps: u32,
+    // 1件をどれだけ表示するか
+    pub entry_secs: f64,
+    // 最後の1件だけ、これだけ長く表示する。
+    pub hold_last_secs: f64,
+    // 最初に何も塗っていない地図をこれだけ表示する。0なら出さない。
+    pub intro_secs: f64,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self {
+            fps: 10,
+            entry_secs: 0.1,
+            hold_last_secs: 0.0,
+            intro_secs: 0.0,
+        }
+    }
+}
+
+impl Timing {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            fps: config.fps,
+            entry_secs: config.entry_secs,
+            hold_last_secs: config.hold_last_secs,
+            intro_secs: config.intro_secs,
+        }
+    }
+
+    pub fn fps(&self) -> u32 {
+        self.fps.max(1)
+    }
+
+    pub fn frames(&self, secs: f64) -> u32 {
+        (secs * self.fps() as f64).round().max(0.0) as u32
+    }
+
+    pub fn entry_frames(&self) -> u32 {
+        self.frames(self.entry_secs).max(1)
+    }
+
+    pub fn intro_frames(&self) -> u32 {
+        self.frames(self.intro_secs)
+    }
+
+    pub fn hold_last_frames(&self) -> u32 {
+        self.frames(self.hold_last_secs)
+    }
+
+    // 丸めの誤差が溜まらないように、区間の長さではなく位置から計算する。
+    pub fn to_ms(&self, frames: u32) -> u64 {
+        frames as u64 * 1000 / self.fps() as u64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+    pub size: u32,
+    pub assets: Assets,
+    pub timing: Timing,
+    pub transition: Transition,
+    // 塗るたびに、この数だけ途中のコマを1フレームずつ挟む。
+    pub transition_frames: u32,
+    // 今回塗った都道府県だけ、そのコマの間ふちを光らせる。
+    pub highlight: Option<Highlight>,
+    // 文字や凡例を描くときに使う、日本語の字形を含むTTF/OTF
+    pub font: Option<PathBuf>,
+    pub text: Option<TextOptions>,
+    pub legend: Option<LegendOptions>,
+    pub labels: Option<LabelOptions>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            size: Resolution::default().as_size(),
+            assets: Assets::embedded(),
+            timing: Timing::default(),
+            transition: Transition::default(),
+            transition_frames: 0,
+            highlight: None,
+            font: None,
+            text: None,
+            legend: None,
+            labels: None,
+        }
+    }
+}
+
+impl RenderOptions {
+    pub fn from_config(config: &Config, assets: &Assets) -> Result<Self> {
+        let text = config.text_options()?;
+        let legend = config.legend_options()?;
+        let labels = config.label_options()?;
+        if (text.is_some() || legend.is_some() || labels.is_some()) && config.font.is_none() {
+            bail!("a font is required to draw text on frames");
+        }
+
+        Ok(Self {
+            size: config.resolution.as_size(),
+            assets: assets.clone(),
+            timing: Timing::from_config(config),
+            transition: config.transition,
+            transition_frames: config.transition_frames,
+            highlight: config.highlight()?,
+            font: config.font.clone(),
+            text,
+            legend,
+            labels,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Frame {
+    // 何件目か (0始まり)。イントロは0。
+    pub index: usize,
+    // タイムライン上の位置と長さ (fps基準のフレーム数)
+    pub start: u32,
+    pub duration: u32,
+    // イントロの白地図ではNone
+    pub pref: Option<Pref>,
+    // 何回目の登場か (1始まり)。イントロは0。
+    pub visit_count: usize,
+    pub tint_color: Option<Rgb<u8>>,
+    // 塗り変わる途中のコマ。この後に同じ件の塗り終わったコマが続く。
+    pub transition: bool,
+    pub img: DynamicImage,
+}
+
+impl Frame {
+    pub fn file_name(&self) -> String {
+        frame_file_name(self.start)
+    }
+
+    // 長く表示するコマは、同じ画像を続けて並べる。その1枚ずつの名前
+    pub fn file_names(&self) -> impl Iterator<Item = String> {
+        (self.start..self.start + self.duration).map(frame_file_name)
+    }
+
+    pub fn info(&self) -> FrameInfo {
+        FrameInfo {
+            index: self.index,
+            start: self.start,
+            duration: self.duration,
+            pref: self.pref.clone(),
+            visit_count: self.visit_count,
+            tint_color: self.tint_color,
+            transition: self.transition,
+        }
+    }
+}
+
+// 連番画像はタイムライン上のフレーム番号で名前をつける。
+pub fn frame_file_name(position: u32) -> String {
+    format!("{:06}.png", position)
+}
+
+// 画像を持たないFrame。書き出し後に字幕などを作るときに使う。
+#[derive(Debug, Clone)]
+pub struct FrameInfo {
+    pub index: usize,
+    pub start: u32,
+    pub duration: u32,
+    pub pref: Option<Pref>,
+    pub visit_count: usize,
+    pub tint_color: Option<Rgb<u8>>,
+    pub transition: bool,
+}
+
+impl FrameInfo {
+    pub fn file_name(&self) -> String {
+        frame_file_name(self.start)
+    }
+
+    pub fn end(&self) -> u32 {
+        self.start + self.duration
+    }
+
+    // 京都府、北海道のような表記
+    pub fn pref_name(&self) -> String {
+        self.pref
+            .as_ref()
+            .map(|pref| format!("{}{}", pref.as_kanji(), pref.suffix()))
+            .unwrap_or_default()
+    }
+
+    // {number}, {index}, {pref}, {count}を埋める。
+    pub fn fill(&self, template: &str) -> String {
+        template
+            .replace("{number}", &(self.index + 1).to_string())
+            .replace("{index}", &self.index.to_string())
+            .replace("{pref}", &self.pref_name())
+            .replace("{count}", &self.visit_count.to_string())
+    }
+}
+
+pub struct Frames {
+    generator: PrefImgGenerator,
+    prefs: std::iter::Enumerate<vec::IntoIter<Pref>>,
+    colors: Vec<Rgb<u8>>,
+    num_of_pref_map: HashMap<Pref, usize>,
+    timing: Timing,
+    transition: Transition,
+    transition_frames: u32,
+    highlight: Option<Highlight>,
+    text: Option<TextOptions>,
+    legend: Option<LegendOptions>,
+    // 凡例で、色ごとに何回目の訪問を表すか
+    legend_visits: Vec<String>,
+    labels: Option<LabelOptions>,
+    need_intro: bool,
+    position: u32,
+    // 途中のコマと塗り終わったコマを、1件分まとめて作って順に返す。
+    pending: VecDeque<Frame>,
+}
+
+impl Frames {
+    fn intro(&mut self) -> Result<Frame> {
+        self.need_intro = false;
+
+        let duration = self.timing.intro_frames();
+        self.position += duration;
+
+        let mut frame = Frame {
+            index: 0,
+            start: 0,
+            duration,
+            pref: None,
+            visit_count: 0,
+            tint_color: None,
+            transition: false,
+            img: DynamicImage::default(),
+        };
+        frame.img = self.decorate(self.generator.get_img(), None, &frame.info())?;
+
+        Ok(frame)
+    }
+
+    // 書き出すコマだけに、光らせたふちと文字を重ねる。
+    fn decorate(
+        &self,
+        img: DynamicImage,
+        glow: Option<&Glow>,
+        info: &FrameInfo,
+    ) -> Result<DynamicImage> {
+        let mut img = match glow {
+            Some(glow) => with_glow(&img, glow),
+            None => img,
+        };
+
+        if let Some(options) = &self.labels {
+            let labels = self
+                .num_of_pref_map
+                .iter()
+                .map(|(pref, &count)| {
+                    let geometry = self.generator.geometry(pref)?;
+                    Ok(Label {
+                        point: geometry.label_point,
+                        area: geometry.area,
+                        text: options.text_of(pref, count),
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            self.generator
+                .draw_labels(&mut img, &labels, &options.style)?;
+        }
+
+        if let Some(text) = &self.text {
+            if let Some(caption) = text.caption.as_ref().filter(|_| info.pref.is_some()) {
+                self.generator
+                    .draw_text(&mut img, &info.fill(caption), &text.caption_style)?;
+            }
+            if let Some(title) = &text.title {
+                self.generator
+                    .draw_text(&mut img, title, &text.title_style)?;
+            }
+            if let Some(footer) = &text.footer {
+                self.generator
+                    .draw_text(&mut img, footer, &text.footer_style)?;
+            }
+        }
+
+        if let Some(legend) = &self.legend {
+            // 最後の1件は、残りがなく途中のコマでもないもの
+            let is_last = info.pref.is_some() && !info.transition && self.prefs.len() == 0;
+            if !legend.last_only || is_last {
+                self.generator
+                    .draw_legend(&mut img, &self.legend_rows(), legend)?;
+            }
+        }
+
+        Ok(img)
+    }
+
+    // 色ごとに、いまその色で塗られている都道府県を数える。
+    fn legend_rows(&self) -> Vec<LegendRow> {
+        let mut counts = vec![0; self.colors.len()];
+        for &visits in self.num_of_pref_map.values().filter(|&&v| v > 0) {
+            counts[(visits - 1) % self.colors.len()] += 1;
+        }
+
+        self.colors
+            .iter()
+            .zip(&self.legend_visits)
+            .zip(counts)
+            .map(|((&color, visits), count)| LegendRow {
+                color,
+                visits: visits.clone(),
+                count,
+            })
+            .collect()
+    }
+
+    fn paint(&mut self, index: usize, pref: Pref) -> Result<()> {
+        let num_of_pref = *self.num_of_pref_map.get(&pref).unwrap_or(&0);
+        self.num_of_pref_map.insert(pref.clone(), num_of_pref + 1);
+
+        let tint_color = self.colors[num_of_pref % self.colors.len()];
+        let prev = (self.transition_frames > 0).then(|| self.generator.get_img());
+        self.generator.overlay(&pref, &tint_color)?;
+        let img = self.generator.get_img();
+        // 光らせるのは書き出すコマだけで、次に塗る元の画像には残さない。
+        let glow = self
+            .highlight
+            .as_ref()
+            .map(|highlight| self.generator.glow(&pref, highlight))
+            .transpose()?;
+
+        let mut frame = Frame {
+            index,
+            start: self.position,
+            duration: 1,
+            pref: Some(pref),
+            visit_count: num_of_pref + 1,
+            tint_color: Some(tint_color),
+            transition: true,
+            img: DynamicImage::default(),
+        };
+
+        if let Some(prev) = prev {
+            let steps = self.transition_frames + 1;
+            for step in 1..steps {
+                let t = step as f32 / steps as f32;
+                let mut step_frame = Frame {
+                    start: self.position,
+                    ..frame.clone()
+                };
+                step_frame.img = self.decorate(
+                    transition_img(&prev, &img, t, self.transition),
+                    glow.as_deref(),
+                    &step_frame.info(),
+                )?;
+                self.pending.push_back(step_frame);
+                self.position += 1;
+            }
+        }
+
+        let mut duration = self.timing.entry_frames();
+        if self.prefs.len() == 0 {
+            duration += self.timing.hold_last_frames();
+        }
+        frame.start = self.position;
+        frame.duration = duration;
+        frame.transition = false;
+        frame.img = self.decorate(img, glow.as_deref(), &frame.info())?;
+        self.position += duration;
+        self.pending.push_back(frame);
+
+        Ok(())
+    }
+}
+
+impl Iterator for Frames {
+    type Item = Result<Frame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.need_intro {
+            return Some(self.intro());
+        }
+
+        if self.pending.is_empty() {
+            let (index, pref) = self.prefs.next()?;
+            if let Err(e) = self.paint(index, pref) {
+                return Some(Err(e));
+            }
+        }
+
+        self.pending.pop_front().map(Ok)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let per_pref = self.transition_frames as usize + 1;
+        let len = self.prefs.len() * per_pref + self.pending.len() + self.need_intro as usize;
+        (len, Some(len))
+    }
+}
+
+impl ExactSizeIterator for Frames {}
+
+pub fn render(input: InputData, options: &RenderOptions) -> Result<Frames> {
+    ensure!(
+        !input.colors.is_empty(),
+        "at least one tint color is required"
+    );
+    let mut generator = PrefImgGenerator::new(&options.assets, options.size)?;
+    generator.preload(&input.prefs)?;
+
+    let mut visits = HashMap::new();
+    for pref in &input.prefs {
+        *visits.entry(pref).or_insert(0) += 1;
+    }
+    let max_visits = visits.into_values().max().unwrap_or_default();
+    let legend_visits = (0..input.colors.len())
+        .map(|idx| visits_of_color(idx, input.colors.len(), max_visits))
+        .collect::<Vec<_>>();
+
+    if let Some(path) = &options.font {
+        let font = load_font(path)?;
+
+        // 字形が足りないことには、塗り始める前に気づけるようにする。
+        // 番号や回数はどの桁が出てもいいように、数字は全部入れておく。
+        let mut used = String::from("0123456789");
+        let prefs = input.prefs.iter().cloned().collect::<HashSet<_>>();
+        let infos = std::iter::once(None)
+            .chain(prefs.into_iter().map(Some))
+            .map(|pref| FrameInfo {
+                index: 0,
+                start: 0,
+                duration: 0,
+                pref,
+                visit_count: 0,
+                tint_color: None,
+                transition: false,
+            })
+            .collect::<Vec<_>>();
+        for template in options
+            .text
+            .iter()
+            .flat_map(|text| [&text.caption, &text.title, &text.footer])
+            .flatten()
+        {
+            for info in &infos {
+                used += &info.fill(template);
+            }
+        }
+        if let Some(legend) = &options.legend {
+            // 幅に収まらない説明は「...」で切られる。
+            used += "...";
+            for visits in &legend_visits {
+                let row = LegendRow {
+                    color: Rgb([0, 0, 0]),
+                    visits: visits.clone(),
+                    count: 0,
+                };
+                used += &row.label(&legend.label);
+            }
+        }
+        if let Some(labels) = &options.labels {
+            for pref in &input.prefs {
+                used += &labels.text_of(pref, 0);
+            }
+        }
+        check_glyphs(&font, &used).map_err(|e| anyhow!("`{}`: {}", path.display(), e))?;
+
+        generator.set_font(font);
+    }
+
+    Ok(Frames {
+        generator,
+        prefs: input.prefs.into_iter().enumerate(),
+        colors: input.colors,
+        num_of_pref_map: HashMap::new(),
+        timing: options.timing.clone(),
+        transition: options.transition,
+        transition_frames: options.transition_frames,
+        highlight: options.highlight.clone(),
+        text: options.text.clone(),
+        legend: options.legend.clone(),
+        legend_visits,
+        labels: options.labels.clone(),
+        need_intro: options.timing.intro_frames() > 0,
+        position: 0,
+        pending: VecDeque::new(),
+    })
+}
@@ -0,0 +1,250 @@
+use std::fs;
+use std::path::Path;
+
+use ab_glyph::{point, Font, FontArc, PxScale, ScaleFont};
+use anyhow::{anyhow, bail, Result};
+use image::{Pixel, Rgb, Rgba, RgbaImage};
+
+use crate::TextPosition;
+
+#[derive(Debug, Clone)]
+pub struct TextStyle {
+    pub position: TextPosition,
+    // 文字の高さ。画像の高さに対する割合
+    pub size: f64,
+    pub color: Rgb<u8>,
+    pub outline_color: Rgb<u8>,
+    // 縁取りの太さ (px)。0なら縁取りしない。
+    pub outline_width: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct TextOptions {
+    // コマごとの見出し。{number}, {index}, {pref}, {count}が使える。イントロには出さない。
+    pub caption: Option<String>,
+    pub title: Option<String>,
+    pub footer: Option<String>,
+    pub caption_style: TextStyle,
+    pub title_style: TextStyle,
+    pub footer_style: TextStyle,
+}
+
+pub fn load_font(path: &Path) -> Result<FontArc> {
+    let bytes =
+        fs::read(path).map_err(|e| anyhow!("failed to read font `{}`: {}", path.display(), e))?;
+
+    FontArc::try_from_vec(bytes).map_err(|_| anyhow!("`{}` is not a TTF/OTF font", path.display()))
+}
+
+pub fn check_glyphs(font: &FontArc, text: &str) -> Result<()> {
+    match text
+        .chars()
+        .find(|&c| !c.is_whitespace() && font.glyph_id(c).0 == 0)
+    {
+        Some(c) => bail!("the font has no glyph for `{}`", c),
+        None => Ok(()),
+    }
+}
+
+// 1pxごとの文字の濃さ (0.0..=1.0)
+pub(crate) struct Coverage {
+    pub width: usize,
+    pub height: usize,
+    values: Vec<f32>,
+}
+
+impl Coverage {
+    fn get(&self, x: usize, y: usize) -> f32 {
+        self.values[y * self.width + x]
+    }
+
+    // 縁取りの分だけ、文字の形を太らせる。
+    fn dilate(&self, radius: u32) -> Self {
+        let r = radius as isize;
+        let mut values = vec![0.0; self.values.len()];
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let mut v: f32 = 0.0;
+                for dy in -r..=r {
+                    for dx in -r..=r {
+                        if dx * dx + dy * dy > r * r {
+                            continue;
+                        }
+                        let (sx, sy) = (x as isize + dx, y as isize + dy);
+                        if sx < 0
+                            || sy < 0
+                            || sx >= self.width as isize
+                            || sy >= self.height as isize
+                        {
+                            continue;
+                        }
+                        v = v.max(self.get(sx as usize, sy as usize));
+                    }
+                }
+                values[y * self.width + x] = v;
+            }
+        }
+
+        Self {
+            width: self.width,
+            height: self.height,
+            values,
+        }
+    }
+}
+
+// 改行で複数行にできる。行揃えは位置に合わせる。
+pub(crate) fn rasterize(
+    font: &FontArc,
+    text: &str,
+    px: f32,
+    position: TextPosition,
+    pad: u32,
+) -> Result<Coverage> {
+    check_glyphs(font, text)?;
+    let font = font.as_scaled(PxScale::from(px));
+    let line_height = font.height() + font.line_gap();
+
+    let lines = text.lines().collect::<Vec<_>>();
+    let line_width = |line: &str| {
+        let mut width: f32 = 0.0;
+        let mut prev = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(prev) = prev {
+                width += font.kern(prev, id);
+            }
+            width += font.h_advance(id);
+            prev = Some(id);
+        }
+        width
+    };
+
+    let widths = lines
+        .iter()
+        .map(|line| line_width(line))
+        .collect::<Vec<_>>();
+    let max_width = widths.iter().cloned().fold(0.0, f32::max);
+    let pad = pad as usize;
+    let width = max_width.ceil() as usize + pad * 2;
+    let height = (line_height * lines.len() as f32).ceil() as usize + pad * 2;
+
+    let mut values = vec![0.0; width * height];
+    for (n, (line, line_width)) in lines.iter().zip(&widths).enumerate() {
+        let mut x = pad as f32
+            + match position {
+                TextPosition::TopLeft | TextPosition::Left | TextPosition::BottomLeft => 0.0,
+                TextPosition::TopRight | TextPosition::Right | TextPosition::BottomRight => {
+                    max_width - line_width
+                }
+                _ => (max_width - line_width) / 2.0,
+            };
+        let baseline = pad as f32 + line_height * n as f32 + font.ascent();
+
+        let mut prev = None;
+        for c in line.chars() {
+            let id = font.glyph_id(c);
+            if let Some(prev) = prev {
+                x += font.kern(prev, id);
+            }
+            let glyph = id.with_scale_and_position(font.scale(), point(x, baseline));
+            x += font.h_advance(id);
+            prev = Some(id);
+
+            let Some(outlined) = font.outline_glyph(glyph) else {
+                continue;
+            };
+            let bounds = outlined.px_bounds();
+            outlined.draw(|gx, gy, c| {
+                let (px, py) = (
+                    bounds.min.x as i32 + gx as i32,
+                    bounds.min.y as i32 + gy as i32,
+                );
+                if px >= 0 && py >= 0 && (px as usize) < width && (py as usize) < height {
+                    let v = &mut values[py as usize * width + px as usize];
+                    *v = (*v + c).min(1.0);
+                }
+            });
+        }
+    }
+
+    Ok(Coverage {
+        width,
+        height,
+        values,
+    })
+}
+
+// 文字を縁取りつきで画像に描く。はみ出した分は切り捨てる。
+pub fn draw_text(img: &mut RgbaImage, font: &FontArc, text: &str, style: &TextStyle) -> Result<()> {
+    if text.trim().is_empty() {
+        return Ok(());
+    }
+
+    let px = text_px(img, style);
+    let coverage = rasterize(font, text, px, style.position, style.outline_width)?;
+    let (x, y) = anchor(img, coverage.width, coverage.height, px, style.position);
+    blit(img, &coverage, x, y, style);
+
+    Ok(())
+}
+
+pub(crate) fn text_px(img: &RgbaImage, style: &TextStyle) -> f32 {
+    (style.size * img.height() as f64).max(1.0) as f32
+}
+
+// 位置に合わせて、w x hの領域を置く左上の座標を決める。
+// 端からの余白は文字の大きさの半分
+pub(crate) fn anchor(
+    img: &RgbaImage,
+    w: usize,
+    h: usize,
+    px: f32,
+    position: TextPosition,
+) -> (i64, i64) {
+    let margin = (px / 2.0) as i64;
+    let (iw, ih) = (img.width() as i64, img.height() as i64);
+    let (w, h) = (w as i64, h as i64);
+
+    let x = match position {
+        TextPosition::TopLeft | TextPosition::Left | TextPosition::BottomLeft => margin,
+        TextPosition::TopRight | TextPosition::Right | TextPosition::BottomRight => iw - w - margin,
+        _ => (iw - w) / 2,
+    };
+    let y = match position {
+        TextPosition::TopLeft | TextPosition::Top | TextPosition::TopRight => margin,
+        TextPosition::Left | TextPosition::Center | TextPosition::Right => (ih - h) / 2,
+        _ => ih - h - margin,
+    };
+
+    (x, y)
+}
+
+pub(crate) fn blit(img: &mut RgbaImage, coverage: &Coverage, x: i64, y: i64, style: &TextStyle) {
+    let (w, h) = img.dimensions();
+    let outline = (style.outline_width > 0).then(|| coverage.dilate(style.outline_width));
+
+    let [r, g, b] = style.color.0;
+    let [or, og, ob] = style.outline_color.0;
+    for cy in 0..coverage.height {
+        for cx in 0..coverage.width {
+            let (px, py) = (x + cx as i64, y + cy as i64);
+            if px < 0 || py < 0 || px >= w as i64 || py >= h as i64 {
+                continue;
+            }
+            let base = img.get_pixel_mut(px as u32, py as u32);
+
+            if let Some(outline) = &outline {
+                let a = outline.get(cx, cy);
+                if a > 0.0 {
+                    base.blend(&Rgba([or, og, ob, (a * 255.0).round() as u8]));
+                }
+            }
+            let a = coverage.get(cx, cy);
+            if a > 0.0 {
+                base.blend(&Rgba([r, g, b, (a * 255.0).round() as u8]));
+            }
+        }
+    }
+}